@@ -25,6 +25,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         name: "test".to_string(),
         email: "test1@example.com".to_string(),
         password: "password123".to_string(),
+        device_id: None,
+        device_name: None,
     };
 
     let user = auth_service.register_user(register_request).await.expect("Register user failed");
@@ -35,6 +37,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         name: "test1".to_string(),
         email: " test1@example.com".to_string(),
         password: "password123".to_string(),
+        device_id: None,
+        device_name: None,
     };
 
     match auth_service.register_user(duplicate_register).await {
@@ -46,11 +50,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let login_request = auth::model::LoginRequest {
         email: "test1@example.com".to_string(),
         password: "password123".to_string(),
+        device_id: None,
+        device_name: None,
     };
 
     let authenticated_user = auth_service.authenticate(
         &login_request.email, 
-        &login_request.password
+        &login_request.password,
+        None,
     ).await.expect("Authenticate failed");
 
     println!("Authenticated user: {:?}", authenticated_user);
@@ -59,18 +66,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let wrong_password = auth::model::LoginRequest {
         email: "test1@example.com".to_string(),
         password: "wrongpassword".to_string(),
+        device_id: None,
+        device_name: None,
     };
 
-    match auth_service.authenticate(&wrong_password.email, &wrong_password.password).await {
+    match auth_service.authenticate(&wrong_password.email, &wrong_password.password, None).await {
         Ok(_) => panic!("Should not authenticate with wrong password"),
         Err(e) => println!("Expected error: {}", e),
     }
 
     // 测试token生成和验证
-    let token = auth_service.generate_token(&user.id).expect("Failed to generate token");
-    println!("Generated token: {}", token);
+    let tokens = auth_service.issue_tokens(&user, auth::model::SessionDevice::default()).await.expect("Failed to generate token");
+    println!("Generated token: {}", tokens.token);
 
-    let claims = auth_service.validate_token(&token).await.expect("failed to validate token");
+    let claims = auth_service.validate_token(&tokens.token).await.expect("failed to validate token");
     println!("Token claims: {:?}", claims);
 
     // 测试获取用户
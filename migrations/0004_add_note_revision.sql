@@ -0,0 +1,2 @@
+-- 笔记版本号, 每次修改递增, 用于冲突检测
+ALTER TABLE notes ADD COLUMN revision BIGINT NOT NULL DEFAULT 1;
@@ -0,0 +1,36 @@
+-- 每个用户的变更序号, 写操作持有该行锁直到提交, 保证序号按提交顺序可见
+CREATE TABLE user_sync_state (
+    user_id VARCHAR(36) PRIMARY KEY,
+    last_seq BIGINT NOT NULL DEFAULT 0,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- 笔记变更日志
+CREATE TABLE note_changes (
+    user_id VARCHAR(36) NOT NULL,
+    seq BIGINT NOT NULL,
+    note_id VARCHAR(36) NOT NULL,
+    kind TEXT NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, seq),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_note_changes_note ON note_changes(user_id, note_id);
+
+-- 为已有笔记和删除记录补充变更日志
+INSERT INTO note_changes (user_id, seq, note_id, kind, changed_at)
+SELECT
+    user_id,
+    ROW_NUMBER() OVER (PARTITION BY user_id ORDER BY changed_at, note_id),
+    note_id,
+    kind,
+    changed_at
+FROM (
+    SELECT user_id, id AS note_id, 'upsert' AS kind, updated_at AS changed_at FROM notes
+    UNION ALL
+    SELECT user_id, note_id, 'delete' AS kind, deleted_at AS changed_at FROM deleted_notes
+) changes;
+
+INSERT INTO user_sync_state (user_id, last_seq)
+SELECT user_id, MAX(seq) FROM note_changes GROUP BY user_id;
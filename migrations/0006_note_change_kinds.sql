@@ -0,0 +1,6 @@
+-- 变更类型细分为 created / updated / deleted, 供实时通知使用
+UPDATE note_changes SET kind = CASE kind
+    WHEN 'upsert' THEN 'updated'
+    WHEN 'delete' THEN 'deleted'
+    ELSE kind
+END;
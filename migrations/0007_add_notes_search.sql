@@ -0,0 +1,7 @@
+-- 全文搜索, 标题权重高于正文; 使用 simple 配置以兼容多语言内容
+ALTER TABLE notes ADD COLUMN search_vector tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('simple', coalesce(title, '')), 'A') ||
+    setweight(to_tsvector('simple', coalesce(content, '')), 'B')
+) STORED;
+
+CREATE INDEX idx_notes_search ON notes USING GIN (search_vector);
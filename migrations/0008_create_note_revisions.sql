@@ -0,0 +1,13 @@
+-- 笔记历史版本, 每次修改前保存上一个版本
+CREATE TABLE note_revisions (
+    note_id VARCHAR(36) NOT NULL,
+    revision BIGINT NOT NULL,
+    user_id VARCHAR(36) NOT NULL,
+    title TEXT NOT NULL,
+    content TEXT NOT NULL,
+    tags TEXT[] NOT NULL DEFAULT '{}',
+    updated_at TIMESTAMPTZ NOT NULL,
+    archived_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (note_id, revision),
+    FOREIGN KEY (note_id) REFERENCES notes(id) ON DELETE CASCADE
+);
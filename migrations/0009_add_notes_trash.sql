@@ -0,0 +1,4 @@
+-- 回收站, deleted_at 不为空的笔记在回收站中
+ALTER TABLE notes ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE INDEX idx_notes_trash ON notes(user_id, deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,4 @@
+-- 已压缩的删除记录的最大序号, 游标早于该序号的客户端需要全量同步
+ALTER TABLE user_sync_state ADD COLUMN compacted_seq BIGINT NOT NULL DEFAULT 0;
+
+CREATE INDEX idx_deleted_notes_deleted_at ON deleted_notes(deleted_at);
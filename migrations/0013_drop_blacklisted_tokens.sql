@@ -0,0 +1,2 @@
+-- 访问令牌改为按会话吊销, 不再保存令牌黑名单
+DROP TABLE blacklisted_tokens;
@@ -0,0 +1,13 @@
+-- 一次性用户令牌 (如密码重置), 只保存令牌哈希
+CREATE TABLE user_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id VARCHAR(36) NOT NULL,
+    purpose VARCHAR(32) NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_user_tokens_user ON user_tokens(user_id, purpose);
+CREATE INDEX idx_user_tokens_expires ON user_tokens(expires_at);
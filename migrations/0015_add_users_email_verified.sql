@@ -0,0 +1,31 @@
+-- 邮箱验证标记, 已有账号视为已验证
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE users SET email_verified = TRUE;
+
+-- 邮箱按小写唯一, 先规范化已有邮箱
+-- 大小写不同的重复邮箱保留最早注册的账号, 其余账号的邮箱改为 "<id>+<邮箱>" 并输出 NOTICE,
+-- 这些账号无法再用原邮箱登录, 需要根据迁移日志人工联系找回
+DO $$
+DECLARE
+    duplicate RECORD;
+BEGIN
+    FOR duplicate IN
+        WITH ranked AS (
+            SELECT id, email, ROW_NUMBER() OVER (
+                PARTITION BY lower(trim(email))
+                ORDER BY created_at ASC, id ASC
+            ) AS rank
+            FROM users
+        )
+        SELECT id, email FROM ranked WHERE rank > 1
+    LOOP
+        UPDATE users
+        SET email = left(duplicate.id || '+' || lower(trim(duplicate.email)), 255)
+        WHERE id = duplicate.id;
+        RAISE NOTICE 'duplicate email % of user % renamed', duplicate.email, duplicate.id;
+    END LOOP;
+END $$;
+
+UPDATE users SET email = lower(trim(email)) WHERE email <> lower(trim(email));
+
+CREATE UNIQUE INDEX idx_users_email_lower ON users(lower(email));
@@ -0,0 +1,21 @@
+-- TOTP 两步验证, enabled_at 为空表示尚未确认
+CREATE TABLE user_mfa (
+    user_id VARCHAR(36) PRIMARY KEY,
+    totp_secret BYTEA NOT NULL,
+    last_used_step BIGINT,
+    created_at TIMESTAMPTZ NOT NULL,
+    enabled_at TIMESTAMPTZ,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- 一次性恢复码, 与密码一样用 Argon2 哈希保存
+CREATE TABLE mfa_recovery_codes (
+    id VARCHAR(36) PRIMARY KEY,
+    user_id VARCHAR(36) NOT NULL,
+    code_hash VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_mfa_recovery_codes_user ON mfa_recovery_codes(user_id);
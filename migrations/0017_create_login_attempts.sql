@@ -0,0 +1,9 @@
+-- 登录失败计数, key 为 email:<邮箱>, ip:<地址> 或 user:<用户ID> (两步验证)
+CREATE TABLE login_attempts (
+    key VARCHAR(300) PRIMARY KEY,
+    failures INTEGER NOT NULL,
+    last_failure_at TIMESTAMPTZ NOT NULL,
+    locked_until TIMESTAMPTZ
+);
+
+CREATE INDEX idx_login_attempts_last_failure ON login_attempts(last_failure_at);
@@ -0,0 +1,2 @@
+-- 用户名不再截断为 10 个字符
+ALTER TABLE users ALTER COLUMN user_name TYPE VARCHAR(50);
@@ -0,0 +1,2 @@
+-- 修改邮箱时待确认的新邮箱, 确认后才写入 users
+ALTER TABLE user_tokens ADD COLUMN new_email VARCHAR(255);
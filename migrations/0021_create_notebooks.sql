@@ -0,0 +1,26 @@
+-- 笔记本, parent_id 为空表示顶层笔记本
+CREATE TABLE notebooks (
+    id VARCHAR(36) PRIMARY KEY,
+    user_id VARCHAR(36) NOT NULL,
+    parent_id VARCHAR(36),
+    name VARCHAR(100) NOT NULL,
+    revision BIGINT NOT NULL DEFAULT 1,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (parent_id) REFERENCES notebooks(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_notebooks_user ON notebooks(user_id);
+CREATE INDEX idx_notebooks_parent ON notebooks(parent_id);
+
+-- 笔记所在的笔记本, 为空表示未归类
+ALTER TABLE notes ADD COLUMN notebook_id VARCHAR(36) REFERENCES notebooks(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_notes_notebook ON notes(notebook_id);
+
+-- 变更日志同时记录笔记本变更, 此时 note_id 为笔记本ID
+ALTER TABLE note_changes ADD COLUMN entity TEXT NOT NULL DEFAULT 'note';
+
+DROP INDEX idx_note_changes_note;
+CREATE INDEX idx_note_changes_entity ON note_changes(user_id, entity, note_id);
@@ -0,0 +1,21 @@
+-- 笔记或笔记本共享给其他用户, 共享笔记本时包含其子笔记本和其中的笔记
+CREATE TABLE shares (
+    id VARCHAR(36) PRIMARY KEY,
+    owner_id VARCHAR(36) NOT NULL,
+    recipient_id VARCHAR(36) NOT NULL,
+    note_id VARCHAR(36),
+    notebook_id VARCHAR(36),
+    permission TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (recipient_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (note_id) REFERENCES notes(id) ON DELETE CASCADE,
+    FOREIGN KEY (notebook_id) REFERENCES notebooks(id) ON DELETE CASCADE,
+    CHECK ((note_id IS NULL) <> (notebook_id IS NULL)),
+    UNIQUE (recipient_id, note_id),
+    UNIQUE (recipient_id, notebook_id)
+);
+
+CREATE INDEX idx_shares_owner ON shares(owner_id);
+CREATE INDEX idx_shares_note ON shares(note_id) WHERE note_id IS NOT NULL;
+CREATE INDEX idx_shares_notebook ON shares(notebook_id) WHERE notebook_id IS NOT NULL;
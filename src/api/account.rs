@@ -0,0 +1,77 @@
+use std::io::{Cursor, Write};
+
+use actix_web::{delete, get, web, HttpResponse, Responder};
+use serde::Serialize;
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use crate::{auth::{error::AuthError, model::AccountDeleteRequest, service::AuthService}, middleware::AuthSession, sync::service::SyncService};
+use crate::log_error;
+
+#[delete("/me")]
+pub async fn delete_account(
+    auth_service: web::Data<AuthService>,
+    user_id: web::ReqData<String>,
+    request: web::Json<AccountDeleteRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting account deletion");
+
+    match auth_service.schedule_account_deletion(&user_id, &request.password).await {
+        Ok(deletion) => {
+            tracing::info!(user_id = %user_id.as_str(), "Account deletion scheduled successfully");
+            Ok(HttpResponse::Accepted().json(deletion))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to schedule account deletion");
+            Err(e)
+        }
+    }
+}
+
+#[get("/me/export")]
+pub async fn export_account(
+    auth_service: web::Data<AuthService>,
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    session: web::ReqData<AuthSession>,
+) -> Result<HttpResponse, actix_web::Error> {
+    tracing::info!("Starting account export");
+
+    let profile = auth_service.account_profile(&user_id, session.id()).await
+        .inspect_err(|e| { log_error!(e, "Failed to export account profile"); })?;
+    let notes = sync_service.export_notes(&user_id).await
+        .inspect_err(|e| { log_error!(e, "Failed to export notes"); })?;
+
+    let archive = build_archive(&[
+        ("profile.json", to_json(&profile)?),
+        ("notebooks.json", to_json(&notes.notebooks)?),
+        ("notes.json", to_json(&notes.notes)?),
+        ("trash.json", to_json(&notes.trash)?),
+        ("revisions.json", to_json(&notes.revisions)?),
+    ])
+    .map_err(|e| {
+        log_error!(e, "Failed to build export archive");
+        actix_web::error::ErrorInternalServerError("Failed to build export archive")
+    })?;
+
+    tracing::info!(user_id = %user_id.as_str(), notes_count = notes.notes.len(), bytes = archive.len(), "Account exported successfully");
+    let file_name = format!("notes-export-{}.zip", chrono::Utc::now().format("%Y%m%d"));
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", file_name)))
+        .body(archive))
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Vec<u8>, actix_web::Error> {
+    serde_json::to_vec_pretty(value).map_err(actix_web::error::ErrorInternalServerError)
+}
+
+// 每个文件压缩后写入同一个 zip 归档
+fn build_archive(files: &[(&str, Vec<u8>)]) -> zip::result::ZipResult<Vec<u8>> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for (name, contents) in files {
+        writer.start_file(*name, options)?;
+        writer.write_all(contents)?;
+    }
+    Ok(writer.finish()?.into_inner())
+}
@@ -1,26 +1,35 @@
-use actix_web::{get, post, web, HttpResponse, Responder};
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse, Responder};
 use serde_json::json;
 
-use crate::{auth::{error::AuthError, model::{AuthResponse, LoginRequest, RegisterRequest}, service::AuthService}, middleware::AuthToken};
+use crate::{auth::{error::AuthError, model::{AuthOutcome, EmailChangeRequest, EmailVerifyRequest, LoginRequest, MfaCodeRequest, MfaDisableRequest, MfaVerifyRequest, PasswordChangeRequest, EmailRequest, PasswordResetRequest, ProfileUpdateRequest, RefreshRequest, RegisterRequest, RevokeSessionsResponse, SessionDevice}, service::AuthService}, middleware::AuthSession};
 use crate::log_error;
 
 #[post("/register")]
 pub async fn register(
+    req: HttpRequest,
     auth_service: web::Data<AuthService>,
     credentials: web::Json<RegisterRequest>,
 ) -> Result<impl Responder, AuthError> {
     tracing::info!("Starting user register");
 
-    match auth_service.register_user(credentials.into_inner()).await {
+    let credentials = credentials.into_inner();
+    let device = session_device(&req, &auth_service, credentials.device_id.clone(), credentials.device_name.clone());
+    match auth_service.register_user(credentials).await {
         Ok(user) => {
-            match auth_service.generate_token(&user.id) {
-                Ok(token) => {
+            match auth_service.issue_tokens(&user, device).await {
+                Ok(response) => {
                     tracing::info!("User signed up successfully");
-                    Ok(HttpResponse::Ok().json(AuthResponse {
-                        token,
-                        user_id: user.id,
-                        user_name: user.user_name
-                    }))
+                    Ok(HttpResponse::Ok().json(response))
+                }
+                // 策略要求先验证邮箱, 注册成功但不签发令牌
+                Err(AuthError::EmailNotVerified) => {
+                    tracing::info!(user_id = %user.id, "User signed up, email verification required");
+                    Ok(HttpResponse::Accepted().json(json!({
+                        "message": "Verify your email to sign in",
+                        "user_id": user.id,
+                    })))
                 }
                 Err(e) => {
                     log_error!(e, "Failed to generate token");
@@ -37,21 +46,19 @@ pub async fn register(
 
 #[get("/login")]
 pub async fn login(
+    req: HttpRequest,
     auth_service: web::Data<AuthService>,
     credentials: web::Json<LoginRequest>,
 ) -> Result<impl Responder, AuthError> {
     tracing::info!("Starting user login");
 
-    match auth_service.authenticate(&credentials.email, &credentials.password).await {
-        Ok(user) => {
-            match auth_service.generate_token(&user.id) {
-                Ok(token) => {
+    let device = session_device(&req, &auth_service, credentials.device_id.clone(), credentials.device_name.clone());
+    match auth_service.authenticate(&credentials.email, &credentials.password, device.ip.as_deref()).await {
+        Ok(AuthOutcome::Authenticated(user)) => {
+            match auth_service.issue_tokens(&user, device).await {
+                Ok(response) => {
                     tracing::info!("User signed in successfully");
-                    Ok(HttpResponse::Ok().json(AuthResponse {
-                        token,
-                        user_id: user.id,
-                        user_name: user.user_name,
-                    }))
+                    Ok(HttpResponse::Ok().json(response))
                 }
                 Err(e) => {
                     log_error!(e, "Failed to generate token");
@@ -59,6 +66,10 @@ pub async fn login(
                 }
             }
         }
+        Ok(AuthOutcome::MfaRequired(challenge)) => {
+            tracing::info!("User sign in requires MFA");
+            Ok(HttpResponse::Ok().json(challenge))
+        }
         Err(e) => {
             log_error!(e, "Failed to sign in");
             Err(e)
@@ -66,6 +77,160 @@ pub async fn login(
     }
 }
 
+#[post("/mfa/verify")]
+pub async fn verify_mfa(
+    req: HttpRequest,
+    auth_service: web::Data<AuthService>,
+    request: web::Json<MfaVerifyRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting MFA verification");
+
+    let device = session_device(&req, &auth_service, request.device_id.clone(), request.device_name.clone());
+    match auth_service.verify_mfa_challenge(&request.challenge_token, &request.code).await {
+        Ok(user) => {
+            match auth_service.issue_tokens(&user, device).await {
+                Ok(response) => {
+                    tracing::info!("User signed in with MFA successfully");
+                    Ok(HttpResponse::Ok().json(response))
+                }
+                Err(e) => {
+                    log_error!(e, "Failed to generate token");
+                    Err(e)
+                }
+            }
+        }
+        Err(e) => {
+            log_error!(e, "Failed to verify MFA");
+            Err(e)
+        }
+    }
+}
+
+#[post("/refresh")]
+pub async fn refresh(
+    auth_service: web::Data<AuthService>,
+    request: web::Json<RefreshRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting token refresh");
+
+    match auth_service.refresh(&request.refresh_token).await {
+        Ok(response) => {
+            tracing::info!(user_id = %response.user_id, "Token refreshed successfully");
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e @ AuthError::RefreshTokenReused(_)) => {
+            tracing::warn!("Refresh token reused, token family revoked");
+            Err(e)
+        }
+        Err(e) => {
+            log_error!(e, "Failed to refresh token");
+            Err(e)
+        }
+    }
+}
+
+#[post("/password/forgot")]
+pub async fn forgot_password(
+    auth_service: web::Data<AuthService>,
+    request: web::Json<EmailRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting password reset request");
+
+    match auth_service.request_password_reset(&request.email).await {
+        Ok(_) => {
+            tracing::info!("Password reset requested successfully");
+            Ok(HttpResponse::Accepted().json(json!({"message": "If the email is registered, a reset token has been sent"})))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to request password reset");
+            Err(e)
+        }
+    }
+}
+
+#[post("/password/reset")]
+pub async fn reset_password(
+    auth_service: web::Data<AuthService>,
+    request: web::Json<PasswordResetRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting password reset");
+
+    match auth_service.reset_password(&request.token, &request.new_password).await {
+        Ok(_) => {
+            tracing::info!("Password reset successfully");
+            Ok(HttpResponse::Ok().json(json!({"message": "Password reset successful"})))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to reset password");
+            Err(e)
+        }
+    }
+}
+
+#[post("/email/verify")]
+pub async fn verify_email(
+    auth_service: web::Data<AuthService>,
+    request: web::Json<EmailVerifyRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting email verification");
+
+    match auth_service.verify_email(&request.token).await {
+        Ok(_) => {
+            tracing::info!("Email verified successfully");
+            Ok(HttpResponse::Ok().json(json!({"message": "Email verified"})))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to verify email");
+            Err(e)
+        }
+    }
+}
+
+#[post("/email/change/confirm")]
+pub async fn confirm_email_change(
+    auth_service: web::Data<AuthService>,
+    request: web::Json<EmailVerifyRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting email change confirmation");
+
+    match auth_service.confirm_email_change(&request.token).await {
+        Ok(_) => {
+            tracing::info!("Email changed successfully");
+            Ok(HttpResponse::Ok().json(json!({"message": "Email changed"})))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to confirm email change");
+            Err(e)
+        }
+    }
+}
+
+#[post("/email/resend")]
+pub async fn resend_verification_email(
+    auth_service: web::Data<AuthService>,
+    request: web::Json<EmailRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting resending verification email");
+
+    match auth_service.resend_verification_email(&request.email).await {
+        Ok(_) => {
+            tracing::info!("Verification email resent successfully");
+            Ok(HttpResponse::Accepted().json(json!({"message": "If the email is registered and unverified, a verification token has been sent"})))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to resend verification email");
+            Err(e)
+        }
+    }
+}
+
+#[get("/.well-known/jwks.json")]
+pub async fn jwks(auth_service: web::Data<AuthService>) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(auth_service.jwks())
+}
+
 #[get("/me")]
 pub async fn get_me(
     auth_service: web::Data<AuthService>,
@@ -73,7 +238,7 @@ pub async fn get_me(
 ) -> Result<impl Responder, AuthError> {
     tracing::info!("Starting getting me");
 
-    match auth_service.get_user_by_id(&user_id.into_inner()).await {
+    match auth_service.get_profile(&user_id).await {
         Ok(user) => {
             tracing::info!(user_id = %user.id, "User get me successfully");
             Ok(HttpResponse::Ok().json(user))
@@ -85,16 +250,37 @@ pub async fn get_me(
     }
 }
 
+#[patch("/me")]
+pub async fn update_me(
+    auth_service: web::Data<AuthService>,
+    user_id: web::ReqData<String>,
+    request: web::Json<ProfileUpdateRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting updating me");
+
+    match auth_service.update_profile(&user_id, request.into_inner()).await {
+        Ok(user) => {
+            tracing::info!(user_id = %user.id, "User profile updated successfully");
+            Ok(HttpResponse::Ok().json(user))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to update me");
+            Err(e)
+        }
+    }
+}
+
 #[post("/logout")]
 pub async fn logout(
     auth_service: web::Data<AuthService>,
-    token: web::ReqData<AuthToken>,
+    user_id: web::ReqData<String>,
+    session: web::ReqData<AuthSession>,
 ) -> Result<impl Responder, AuthError> {
     tracing::info!("Starting user logout");
 
-    match auth_service.logout(token.token()).await {
+    match auth_service.logout(&user_id, session.id()).await {
         Ok(_) => {
-            tracing::info!(token = %token.token(), "User signed out successfully");
+            tracing::info!(session_id = %session.id(), "User signed out successfully");
             Ok(HttpResponse::Ok().json(json!({"message": "Logout successful"})))
         }
         Err(e) => {
@@ -102,4 +288,187 @@ pub async fn logout(
             Err(e)
         }
     }
-}
\ No newline at end of file
+}
+
+#[post("/password")]
+pub async fn change_password(
+    auth_service: web::Data<AuthService>,
+    user_id: web::ReqData<String>,
+    session: web::ReqData<AuthSession>,
+    request: web::Json<PasswordChangeRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting password change");
+
+    match auth_service.change_password(&user_id, session.id(), &request.current_password, &request.new_password).await {
+        Ok(_) => {
+            tracing::info!(user_id = %user_id.as_str(), "Password changed successfully");
+            Ok(HttpResponse::Ok().json(json!({"message": "Password changed"})))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to change password");
+            Err(e)
+        }
+    }
+}
+
+#[post("/email/change")]
+pub async fn request_email_change(
+    auth_service: web::Data<AuthService>,
+    user_id: web::ReqData<String>,
+    request: web::Json<EmailChangeRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting email change");
+
+    match auth_service.request_email_change(&user_id, &request.new_email, &request.password).await {
+        Ok(_) => {
+            tracing::info!(user_id = %user_id.as_str(), "Email change confirmation sent successfully");
+            Ok(HttpResponse::Accepted().json(json!({"message": "A confirmation token has been sent to the new email"})))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to request email change");
+            Err(e)
+        }
+    }
+}
+
+#[post("/mfa/totp")]
+pub async fn enroll_totp(
+    auth_service: web::Data<AuthService>,
+    user_id: web::ReqData<String>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting TOTP enrollment");
+
+    match auth_service.enroll_totp(&user_id).await {
+        Ok(enrollment) => {
+            tracing::info!(user_id = %user_id.as_str(), "TOTP enrollment started successfully");
+            Ok(HttpResponse::Ok().json(enrollment))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to enroll TOTP");
+            Err(e)
+        }
+    }
+}
+
+#[post("/mfa/totp/confirm")]
+pub async fn confirm_totp(
+    auth_service: web::Data<AuthService>,
+    user_id: web::ReqData<String>,
+    request: web::Json<MfaCodeRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting TOTP confirmation");
+
+    match auth_service.confirm_totp(&user_id, &request.code).await {
+        Ok(recovery_codes) => {
+            tracing::info!(user_id = %user_id.as_str(), "TOTP enabled successfully");
+            Ok(HttpResponse::Ok().json(recovery_codes))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to confirm TOTP");
+            Err(e)
+        }
+    }
+}
+
+#[delete("/mfa/totp")]
+pub async fn disable_mfa(
+    auth_service: web::Data<AuthService>,
+    user_id: web::ReqData<String>,
+    request: web::Json<MfaDisableRequest>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting MFA disabling");
+
+    match auth_service.disable_mfa(&user_id, &request.password).await {
+        Ok(_) => {
+            tracing::info!(user_id = %user_id.as_str(), "MFA disabled successfully");
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Err(e) => {
+            log_error!(e, "Failed to disable MFA");
+            Err(e)
+        }
+    }
+}
+
+#[get("/sessions")]
+pub async fn list_sessions(
+    auth_service: web::Data<AuthService>,
+    user_id: web::ReqData<String>,
+    session: web::ReqData<AuthSession>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting listing sessions");
+
+    match auth_service.list_sessions(&user_id, session.id()).await {
+        Ok(sessions) => {
+            tracing::info!(user_id = %user_id.as_str(), count = sessions.len(), "Sessions listed successfully");
+            Ok(HttpResponse::Ok().json(sessions))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to list sessions");
+            Err(e)
+        }
+    }
+}
+
+#[delete("/sessions/{session_id}")]
+pub async fn revoke_session(
+    auth_service: web::Data<AuthService>,
+    user_id: web::ReqData<String>,
+    path: web::Path<String>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting revoking session");
+
+    let session_id = path.into_inner();
+    match auth_service.revoke_session(&user_id, &session_id).await {
+        Ok(_) => {
+            tracing::info!(user_id = %user_id.as_str(), session_id = %session_id, "Session revoked successfully");
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Err(e) => {
+            log_error!(e, "Failed to revoke session");
+            Err(e)
+        }
+    }
+}
+
+#[delete("/sessions")]
+pub async fn revoke_other_sessions(
+    auth_service: web::Data<AuthService>,
+    user_id: web::ReqData<String>,
+    session: web::ReqData<AuthSession>,
+) -> Result<impl Responder, AuthError> {
+    tracing::info!("Starting revoking other sessions");
+
+    match auth_service.revoke_other_sessions(&user_id, session.id()).await {
+        Ok(revoked_sessions) => {
+            tracing::info!(user_id = %user_id.as_str(), revoked_sessions, "Other sessions revoked successfully");
+            Ok(HttpResponse::Ok().json(RevokeSessionsResponse { revoked_sessions }))
+        }
+        Err(e) => {
+            log_error!(e, "Failed to revoke other sessions");
+            Err(e)
+        }
+    }
+}
+
+// 从请求中收集会话的设备信息
+fn session_device(req: &HttpRequest, auth_service: &AuthService, device_id: Option<String>, device_name: Option<String>) -> SessionDevice {
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let ip = client_ip(req, auth_service.trusts_proxy_headers()).map(|ip| ip.to_string());
+
+    SessionDevice { device_id, device_name, user_agent, ip }
+}
+
+// 客户端 IP, 只有信任代理时才读取 Forwarded / X-Forwarded-For 头, 否则使用连接的对端地址
+fn client_ip(req: &HttpRequest, trust_proxy_headers: bool) -> Option<IpAddr> {
+    if !trust_proxy_headers {
+        return req.peer_addr().map(|addr| addr.ip());
+    }
+
+    let info = req.connection_info();
+    let addr = info.realip_remote_addr()?;
+    addr.parse::<IpAddr>().ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
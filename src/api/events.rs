@@ -0,0 +1,221 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{rt, web, web::Bytes, HttpRequest, HttpResponse};
+use actix_ws::{Message, MessageStream, Session};
+use futures_util::stream;
+use serde::Serialize;
+use tokio::sync::{broadcast::{self, error::RecvError}, mpsc};
+
+use crate::sync::{model::NoteChange, service::SyncService};
+use crate::log_error;
+
+// 心跳间隔, 超过客户端超时时间未收到 pong 则断开
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+
+// SSE 保活注释间隔, 防止代理因空闲断开连接
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+// 断线重连时最多补发的变更数, 超出时要求客户端重新同步
+const REPLAY_LIMIT: i64 = 1000;
+
+/// 推送给客户端的消息
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum EventMessage<'a> {
+    /// 笔记变更
+    Change(&'a NoteChange),
+    /// 服务端丢弃了部分事件, 客户端需要调用同步接口补齐
+    Resync,
+}
+
+pub async fn notes_ws(
+    req: HttpRequest,
+    body: web::Payload,
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    tracing::info!(user_id = %user_id, "Opening notes websocket");
+
+    let (response, session, msg_stream) = actix_ws::handle(&req, body)?;
+    // 在握手完成前订阅, 避免遗漏期间的变更
+    let receiver = sync_service.subscribe();
+    rt::spawn(run_ws_session(user_id, session, msg_stream, receiver));
+
+    Ok(response)
+}
+
+async fn run_ws_session(
+    user_id: String,
+    mut session: Session,
+    mut msg_stream: MessageStream,
+    mut receiver: broadcast::Receiver<NoteChange>,
+) {
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_pong = Instant::now();
+
+    let reason = loop {
+        tokio::select! {
+            msg = msg_stream.recv() => match msg {
+                Some(Ok(Message::Ping(bytes))) => {
+                    if session.pong(&bytes).await.is_err() {
+                        break None;
+                    }
+                }
+                Some(Ok(Message::Pong(_))) => last_pong = Instant::now(),
+                Some(Ok(Message::Close(reason))) => break reason,
+                // 该连接只用于推送, 忽略客户端的其他消息
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    log_error!(e, "Websocket protocol error");
+                    break None;
+                }
+                None => break None,
+            },
+            event = receiver.recv() => {
+                let message = match event {
+                    Ok(ref change) if change.user_id == user_id => EventMessage::Change(change),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(user_id = %user_id, skipped, "Websocket subscriber lagged");
+                        EventMessage::Resync
+                    }
+                    Err(RecvError::Closed) => break None,
+                };
+                let payload = serde_json::to_string(&message).expect("Failed to serialize event");
+                if session.text(payload).await.is_err() {
+                    break None;
+                }
+            }
+            _ = heartbeat.tick() => {
+                if last_pong.elapsed() > CLIENT_TIMEOUT {
+                    tracing::debug!(user_id = %user_id, "Websocket client timed out");
+                    break None;
+                }
+                if session.ping(b"").await.is_err() {
+                    break None;
+                }
+            }
+        }
+    };
+
+    let _ = session.close(reason).await;
+    tracing::info!(user_id = %user_id, "Notes websocket closed");
+}
+
+pub async fn notes_events(
+    req: HttpRequest,
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+) -> HttpResponse {
+    let user_id = user_id.into_inner();
+    let last_event_id = req.headers().get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<i64>().ok());
+    tracing::info!(user_id = %user_id, last_event_id = ?last_event_id, "Opening notes event stream");
+
+    // 先订阅再补发, 补发期间的变更按序号去重
+    let receiver = sync_service.subscribe();
+    let (sender, events) = mpsc::channel::<Bytes>(64);
+    rt::spawn(run_event_stream(user_id, last_event_id, sync_service, receiver, sender));
+
+    let body = stream::unfold(events, |mut events| async move {
+        events.recv().await.map(|bytes| (Ok::<_, actix_web::Error>(bytes), events))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(body)
+}
+
+async fn run_event_stream(
+    user_id: String,
+    last_event_id: Option<i64>,
+    sync_service: web::Data<SyncService>,
+    mut receiver: broadcast::Receiver<NoteChange>,
+    sender: mpsc::Sender<Bytes>,
+) {
+    // 客户端重连等待时间
+    if sender.send(Bytes::from_static(b"retry: 3000\n\n")).await.is_err() {
+        return;
+    }
+
+    // 补发断线期间的变更, 无法完整补发时要求客户端重新同步
+    let mut last_seq = last_event_id.unwrap_or(0);
+    if let Some(after) = last_event_id {
+        let replay = match sync_service.changes_since(&user_id, after, REPLAY_LIMIT).await {
+            Ok(Some(changes)) if (changes.len() as i64) < REPLAY_LIMIT => Some(changes),
+            Ok(Some(_)) => {
+                tracing::warn!(user_id = %user_id, after, "Too many changes to replay");
+                None
+            }
+            Ok(None) => {
+                tracing::warn!(user_id = %user_id, after, "Changes to replay have been compacted");
+                None
+            }
+            Err(e) => {
+                log_error!(e, "Failed to replay note changes");
+                None
+            }
+        };
+
+        let messages = match replay {
+            Some(changes) => changes.iter()
+                .map(|change| {
+                    last_seq = change.seq;
+                    change_event(change)
+                })
+                .collect(),
+            None => vec![sse_event("resync", None, "{}")],
+        };
+        for message in messages {
+            if sender.send(message).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    let mut keep_alive = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+    keep_alive.tick().await;
+
+    loop {
+        let bytes = tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(change) if change.user_id == user_id && change.seq > last_seq => {
+                    last_seq = change.seq;
+                    change_event(&change)
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(user_id = %user_id, skipped, "Event stream subscriber lagged");
+                    sse_event("resync", None, "{}")
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = keep_alive.tick() => Bytes::from_static(b": keep-alive\n\n"),
+        };
+
+        // 客户端断开后接收端被释放, 发送失败即退出
+        if sender.send(bytes).await.is_err() {
+            break;
+        }
+    }
+
+    tracing::info!(user_id = %user_id, "Notes event stream closed");
+}
+
+fn change_event(change: &NoteChange) -> Bytes {
+    let data = serde_json::to_string(change).expect("Failed to serialize event");
+    sse_event("change", Some(change.seq), &data)
+}
+
+fn sse_event(event: &str, id: Option<i64>, data: &str) -> Bytes {
+    let mut message = String::new();
+    if let Some(id) = id {
+        message.push_str(&format!("id: {}\n", id));
+    }
+    message.push_str(&format!("event: {}\ndata: {}\n\n", event, data));
+    Bytes::from(message)
+}
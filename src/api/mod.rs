@@ -1,2 +1,8 @@
+pub mod account;
 pub mod auth;
-pub mod sync;
\ No newline at end of file
+pub mod sync;
+pub mod events;
+pub mod tags;
+pub mod notebooks;
+pub mod shares;
+pub mod trash;
\ No newline at end of file
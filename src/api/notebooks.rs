@@ -0,0 +1,143 @@
+use actix_web::{web, HttpResponse, Responder};
+
+use crate::sync::{error::SyncError, model::{NotebookCreate, NotebookMove, NotebookUpdate}, service::SyncService};
+use crate::log_error;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/notebooks")
+            .route("", web::get().to(list_notebooks))
+            .service(
+                web::resource("/{notebook_id}")
+                    .post(create_notebook)
+                    .get(get_notebook)
+                    .put(update_notebook)
+                    .delete(delete_notebook)
+            )
+            .service(
+                web::resource("/{notebook_id}/move")
+                    .post(move_notebook)
+            )
+    );
+}
+
+async fn list_notebooks(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("List notebooks for user {}", user_id.as_str());
+
+    match sync_service.list_notebooks(&user_id).await {
+        Ok(notebooks) => {
+            tracing::info!(notebooks_count = notebooks.len(), "Notebooks listed successfully");
+            Ok(HttpResponse::Ok().json(notebooks))
+        }
+        Err(e) => {
+            log_error!(e, "List notebooks failed");
+            Err(e)
+        }
+    }
+}
+
+async fn create_notebook(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    notebook_id: web::Path<String>,
+    notebook: web::Json<NotebookCreate>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Create notebook {} for user {}", notebook_id, user_id.as_str());
+
+    match sync_service.create_notebook(&user_id, &notebook_id, notebook.into_inner()).await {
+        Ok(notebook) => {
+            tracing::info!(notebook_id = %notebook_id, "Notebook created successfully");
+            Ok(HttpResponse::Created().json(notebook))
+        }
+        Err(e) => {
+            log_error!(e, "Create notebook failed");
+            Err(e)
+        }
+    }
+}
+
+async fn get_notebook(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    notebook_id: web::Path<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Get notebook {} for user {}", notebook_id, user_id.as_str());
+
+    match sync_service.get_notebook(&user_id, &notebook_id).await {
+        Ok(notebook) => {
+            tracing::info!(notebook_id = %notebook_id, "Notebook fetched successfully");
+            Ok(HttpResponse::Ok().json(notebook))
+        }
+        Err(e) => {
+            log_error!(e, "Get notebook failed");
+            Err(e)
+        }
+    }
+}
+
+async fn update_notebook(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    notebook_id: web::Path<String>,
+    update: web::Json<NotebookUpdate>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Rename notebook {} to {} for user {}", notebook_id, update.name, user_id.as_str());
+
+    match sync_service.update_notebook(&user_id, &notebook_id, update.into_inner()).await {
+        Ok(notebook) => {
+            tracing::info!(notebook_id = %notebook_id, "Notebook renamed successfully");
+            Ok(HttpResponse::Ok().json(notebook))
+        }
+        Err(e) => {
+            log_error!(e, "Rename notebook failed");
+            Err(e)
+        }
+    }
+}
+
+async fn move_notebook(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    notebook_id: web::Path<String>,
+    notebook_move: web::Json<NotebookMove>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Move notebook {} to {:?} for user {}", notebook_id, notebook_move.parent_id, user_id.as_str());
+
+    match sync_service.move_notebook(&user_id, &notebook_id, notebook_move.into_inner()).await {
+        Ok(notebook) => {
+            tracing::info!(notebook_id = %notebook_id, "Notebook moved successfully");
+            Ok(HttpResponse::Ok().json(notebook))
+        }
+        Err(e) => {
+            log_error!(e, "Move notebook failed");
+            Err(e)
+        }
+    }
+}
+
+async fn delete_notebook(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    notebook_id: web::Path<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Delete notebook {} for user {}", notebook_id, user_id.as_str());
+
+    match sync_service.delete_notebook(&user_id, &notebook_id).await {
+        Ok(response) => {
+            tracing::info!(
+                notebook_id = %notebook_id,
+                deleted_notebooks = response.deleted_notebooks,
+                trashed_notes = response.trashed_notes,
+                "Notebook deleted successfully"
+            );
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e) => {
+            log_error!(e, "Delete notebook failed");
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use actix_web::{web, HttpResponse, Responder};
+
+use crate::sync::{error::SyncError, model::ShareCreate, service::SyncService};
+use crate::log_error;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/shares")
+            .route("", web::get().to(list_shares))
+            .route("", web::post().to(create_share))
+            .route("/received", web::get().to(list_received_shares))
+            .route("/{share_id}", web::delete().to(delete_share))
+    );
+}
+
+async fn list_shares(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("List shares for user {}", user_id.as_str());
+
+    match sync_service.list_shares(&user_id).await {
+        Ok(shares) => {
+            tracing::info!(shares_count = shares.len(), "Shares listed successfully");
+            Ok(HttpResponse::Ok().json(shares))
+        }
+        Err(e) => {
+            log_error!(e, "List shares failed");
+            Err(e)
+        }
+    }
+}
+
+async fn create_share(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    share: web::Json<ShareCreate>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Create share for user {}", user_id.as_str());
+
+    match sync_service.create_share(&user_id, share.into_inner()).await {
+        Ok(share) => {
+            tracing::info!(share_id = %share.id, recipient_id = %share.recipient_id, "Share created successfully");
+            Ok(HttpResponse::Created().json(share))
+        }
+        Err(e) => {
+            log_error!(e, "Create share failed");
+            Err(e)
+        }
+    }
+}
+
+async fn list_received_shares(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("List received shares for user {}", user_id.as_str());
+
+    match sync_service.list_received_shares(&user_id).await {
+        Ok(shares) => {
+            tracing::info!(shares_count = shares.len(), "Received shares listed successfully");
+            Ok(HttpResponse::Ok().json(shares))
+        }
+        Err(e) => {
+            log_error!(e, "List received shares failed");
+            Err(e)
+        }
+    }
+}
+
+async fn delete_share(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    share_id: web::Path<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Delete share {} for user {}", share_id, user_id.as_str());
+
+    match sync_service.delete_share(&user_id, &share_id).await {
+        Ok(()) => {
+            tracing::info!(share_id = %share_id, "Share deleted successfully");
+            Ok(HttpResponse::Ok().finish())
+        }
+        Err(e) => {
+            log_error!(e, "Delete share failed");
+            Err(e)
+        }
+    }
+}
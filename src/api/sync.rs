@@ -1,12 +1,16 @@
 use actix_web::{web, FromRequest, HttpMessage, HttpResponse, Responder};
 
-use crate::sync::{error::SyncError, model::{NoteCreate, NoteImport, NoteUpdate, SyncRequest}, service::SyncService};
+use crate::sync::{error::SyncError, model::{NoteCreate, NoteImport, NoteListQuery, NoteMove, NoteRevisionDiffQuery, NoteSearchQuery, NoteUpdate, SyncRequest}, service::SyncService};
 use crate::log_error;
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/notes")
+            .route("", web::get().to(list_notes))
             .route("/sync", web::post().to(sync_notes))
+            .route("/ws", web::get().to(super::events::notes_ws))
+            .route("/events", web::get().to(super::events::notes_events))
+            .route("/search", web::get().to(search_notes))
             .service(
                 web::resource("/{note_id}")
                     .post(create_note)
@@ -18,6 +22,26 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
                 web::resource("/{note_id}/import")
                 .post(import_note)
             )
+            .service(
+                web::resource("/{note_id}/move")
+                    .post(move_note)
+            )
+            .service(
+                web::resource("/{note_id}/revisions")
+                    .get(list_note_revisions)
+            )
+            .service(
+                web::resource("/{note_id}/revisions/diff")
+                    .get(diff_note_revisions)
+            )
+            .service(
+                web::resource("/{note_id}/revisions/{revision}")
+                    .get(get_note_revision)
+            )
+            .service(
+                web::resource("/{note_id}/revisions/{revision}/restore")
+                    .post(restore_note_revision)
+            )
     );
 }
 
@@ -101,6 +125,26 @@ async fn update_note(
     }
 }
 
+async fn move_note(
+    sync_service: web::Data<SyncService>,
+    user: AuthenticatedUser,
+    note_id: web::Path<String>,
+    note_move: web::Json<NoteMove>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Move note {} to notebook {:?} for user {}", note_id, note_move.notebook_id, user.0);
+
+    match sync_service.move_note(&user.0, &note_id, note_move.into_inner()).await {
+        Ok(note) => {
+            tracing::info!(note_id = %note_id, "Note moved successfully");
+            Ok(HttpResponse::Ok().json(note))
+        }
+        Err(e) => {
+            log_error!(e, "Move note failed");
+            Err(e)
+        }
+    }
+}
+
 async fn delete_note(
     sync_service: web::Data<SyncService>,
     user: AuthenticatedUser,
@@ -120,6 +164,123 @@ async fn delete_note(
     }
 }
 
+async fn list_note_revisions(
+    sync_service: web::Data<SyncService>,
+    user: AuthenticatedUser,
+    note_id: web::Path<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("List revisions of note {} for user {}", note_id, user.0);
+
+    match sync_service.list_note_revisions(&user.0, &note_id).await {
+        Ok(revisions) => {
+            tracing::info!(note_id = %note_id, revisions_count = revisions.len(), "Note revisions listed successfully");
+            Ok(HttpResponse::Ok().json(revisions))
+        }
+        Err(e) => {
+            log_error!(e, "List note revisions failed");
+            Err(e)
+        }
+    }
+}
+
+async fn get_note_revision(
+    sync_service: web::Data<SyncService>,
+    user: AuthenticatedUser,
+    path: web::Path<(String, i64)>,
+) -> Result<impl Responder, SyncError> {
+    let (note_id, revision) = path.into_inner();
+    tracing::debug!("Get revision {} of note {} for user {}", revision, note_id, user.0);
+
+    match sync_service.get_note_revision(&user.0, &note_id, revision).await {
+        Ok(note_revision) => {
+            tracing::info!(note_id = %note_id, revision, "Note revision gotten successfully");
+            Ok(HttpResponse::Ok().json(note_revision))
+        }
+        Err(e) => {
+            log_error!(e, "Get note revision failed");
+            Err(e)
+        }
+    }
+}
+
+async fn diff_note_revisions(
+    sync_service: web::Data<SyncService>,
+    user: AuthenticatedUser,
+    note_id: web::Path<String>,
+    query: web::Query<NoteRevisionDiffQuery>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Diff revisions {} and {} of note {} for user {}", query.from, query.to, note_id, user.0);
+
+    match sync_service.diff_note_revisions(&user.0, &note_id, query.into_inner()).await {
+        Ok(diff) => {
+            tracing::info!(note_id = %note_id, "Note revisions diffed successfully");
+            Ok(HttpResponse::Ok().json(diff))
+        }
+        Err(e) => {
+            log_error!(e, "Diff note revisions failed");
+            Err(e)
+        }
+    }
+}
+
+async fn restore_note_revision(
+    sync_service: web::Data<SyncService>,
+    user: AuthenticatedUser,
+    path: web::Path<(String, i64)>,
+) -> Result<impl Responder, SyncError> {
+    let (note_id, revision) = path.into_inner();
+    tracing::debug!("Restore revision {} of note {} for user {}", revision, note_id, user.0);
+
+    match sync_service.restore_note_revision(&user.0, &note_id, revision).await {
+        Ok(note) => {
+            tracing::info!(note_id = %note_id, revision, new_revision = note.revision, "Note revision restored successfully");
+            Ok(HttpResponse::Ok().json(note))
+        }
+        Err(e) => {
+            log_error!(e, "Restore note revision failed");
+            Err(e)
+        }
+    }
+}
+
+async fn list_notes(
+    sync_service: web::Data<SyncService>,
+    user: AuthenticatedUser,
+    query: web::Query<NoteListQuery>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("List notes for user {}", user.0);
+
+    match sync_service.list_notes(&user.0, query.into_inner()).await {
+        Ok(response) => {
+            tracing::info!(notes_count = response.notes.len(), "Notes listed successfully");
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e) => {
+            log_error!(e, "List notes failed");
+            Err(e)
+        }
+    }
+}
+
+async fn search_notes(
+    sync_service: web::Data<SyncService>,
+    user: AuthenticatedUser,
+    query: web::Query<NoteSearchQuery>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Search notes for user {}", user.0);
+
+    match sync_service.search_notes(&user.0, query.into_inner()).await {
+        Ok(response) => {
+            tracing::info!(hits_count = response.hits.len(), "Notes searched successfully");
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e) => {
+            log_error!(e, "Search notes failed");
+            Err(e)
+        }
+    }
+}
+
 async fn sync_notes(
     sync_service: web::Data<SyncService>,
     user: AuthenticatedUser,
@@ -127,15 +288,20 @@ async fn sync_notes(
 ) -> Result<impl Responder, SyncError> {
     tracing::info!(
         user_id = %user.0,
-        last_sync = ?sync_request.last_sync_time,
+        cursor = ?sync_request.cursor,
+        changes_count = sync_request.changes.len(),
         "Starting notes sync"
     );
 
     match sync_service.sync_notes(&user.0, sync_request.into_inner()).await {
         Ok(response ) => {
             tracing::info!(
+                results_count = response.results.len(),
                 notes_count = response.notes.len(),
                 deleted_count = response.deleted_note_ids.len(),
+                notebooks_count = response.notebooks.len(),
+                deleted_notebooks_count = response.deleted_notebook_ids.len(),
+                has_more = response.has_more,
                 "Sync completed"
             );
             Ok(HttpResponse::Ok().json(response))
@@ -0,0 +1,93 @@
+use actix_web::{web, HttpResponse, Responder};
+
+use crate::sync::{error::SyncError, model::{TagMerge, TagRename}, service::SyncService};
+use crate::log_error;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/tags")
+            .route("", web::get().to(list_tags))
+            .route("/merge", web::post().to(merge_tags))
+            .service(
+                web::resource("/{tag}")
+                    .put(rename_tag)
+                    .delete(delete_tag)
+            )
+    );
+}
+
+async fn list_tags(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("List tags for user {}", user_id.as_str());
+
+    match sync_service.list_tags(&user_id).await {
+        Ok(tags) => {
+            tracing::info!(tags_count = tags.len(), "Tags listed successfully");
+            Ok(HttpResponse::Ok().json(tags))
+        }
+        Err(e) => {
+            log_error!(e, "List tags failed");
+            Err(e)
+        }
+    }
+}
+
+async fn rename_tag(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    tag: web::Path<String>,
+    rename: web::Json<TagRename>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Rename tag {} to {} for user {}", tag, rename.name, user_id.as_str());
+
+    match sync_service.rename_tag(&user_id, &tag, rename.into_inner()).await {
+        Ok(response) => {
+            tracing::info!(tag = %tag, affected_notes = response.affected_notes, "Tag renamed successfully");
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e) => {
+            log_error!(e, "Rename tag failed");
+            Err(e)
+        }
+    }
+}
+
+async fn merge_tags(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    merge: web::Json<TagMerge>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Merge tag {} into {} for user {}", merge.source, merge.target, user_id.as_str());
+
+    match sync_service.merge_tags(&user_id, merge.into_inner()).await {
+        Ok(response) => {
+            tracing::info!(affected_notes = response.affected_notes, "Tags merged successfully");
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e) => {
+            log_error!(e, "Merge tags failed");
+            Err(e)
+        }
+    }
+}
+
+async fn delete_tag(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    tag: web::Path<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Delete tag {} for user {}", tag, user_id.as_str());
+
+    match sync_service.delete_tag(&user_id, &tag).await {
+        Ok(response) => {
+            tracing::info!(tag = %tag, affected_notes = response.affected_notes, "Tag deleted successfully");
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e) => {
+            log_error!(e, "Delete tag failed");
+            Err(e)
+        }
+    }
+}
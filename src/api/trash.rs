@@ -0,0 +1,97 @@
+use actix_web::{web, HttpResponse, Responder};
+
+use crate::sync::{error::SyncError, service::SyncService};
+use crate::log_error;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/trash")
+            .service(
+                web::resource("")
+                    .get(list_trash)
+                    .delete(empty_trash)
+            )
+            .service(
+                web::resource("/{note_id}")
+                    .delete(purge_trashed_note)
+            )
+            .service(
+                web::resource("/{note_id}/restore")
+                    .post(restore_note)
+            )
+    );
+}
+
+async fn list_trash(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("List trash for user {}", user_id.as_str());
+
+    match sync_service.list_trash(&user_id).await {
+        Ok(notes) => {
+            tracing::info!(notes_count = notes.len(), "Trash listed successfully");
+            Ok(HttpResponse::Ok().json(notes))
+        }
+        Err(e) => {
+            log_error!(e, "List trash failed");
+            Err(e)
+        }
+    }
+}
+
+async fn restore_note(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    note_id: web::Path<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Restore note {} from trash for user {}", note_id, user_id.as_str());
+
+    match sync_service.restore_note(&user_id, &note_id).await {
+        Ok(note) => {
+            tracing::info!(note_id = %note_id, "Note restored successfully");
+            Ok(HttpResponse::Ok().json(note))
+        }
+        Err(e) => {
+            log_error!(e, "Restore note failed");
+            Err(e)
+        }
+    }
+}
+
+async fn purge_trashed_note(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+    note_id: web::Path<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Permanently delete note {} for user {}", note_id, user_id.as_str());
+
+    match sync_service.purge_trashed_note(&user_id, &note_id).await {
+        Ok(_) => {
+            tracing::info!(note_id = %note_id, "Note permanently deleted");
+            Ok(HttpResponse::Ok().finish())
+        }
+        Err(e) => {
+            log_error!(e, "Permanently delete note failed");
+            Err(e)
+        }
+    }
+}
+
+async fn empty_trash(
+    sync_service: web::Data<SyncService>,
+    user_id: web::ReqData<String>,
+) -> Result<impl Responder, SyncError> {
+    tracing::debug!("Empty trash for user {}", user_id.as_str());
+
+    match sync_service.empty_trash(&user_id).await {
+        Ok(response) => {
+            tracing::info!(purged_notes = response.purged_notes, "Trash emptied successfully");
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e) => {
+            log_error!(e, "Empty trash failed");
+            Err(e)
+        }
+    }
+}
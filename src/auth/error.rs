@@ -29,6 +29,49 @@ pub enum AuthError {
 
     #[display("Unauthorized")]
     Unauthorized,
+
+    #[display("Invalid refresh token")]
+    InvalidRefreshToken,
+
+    // 携带被吊销的会话 id, 供服务层同步清理会话缓存
+    #[display("Refresh token reused")]
+    RefreshTokenReused(String),
+
+    #[display("Session not found")]
+    SessionNotFound,
+
+    #[display("Invalid or expired token")]
+    InvalidToken,
+
+    #[display("Mail delivery error: {}", _0)]
+    MailError(std::io::Error),
+
+    #[display("Invalid email")]
+    InvalidEmail,
+
+    #[display("Email not verified")]
+    EmailNotVerified,
+
+    #[display("Email already in use")]
+    EmailInUse,
+
+    #[display("Invalid user name: {}", _0)]
+    InvalidUserName(String),
+
+    #[display("Invalid MFA code")]
+    InvalidMfaCode,
+
+    #[display("MFA already enabled")]
+    MfaAlreadyEnabled,
+
+    #[display("MFA not enabled")]
+    MfaNotEnabled,
+
+    #[display("TOTP error: {}", _0)]
+    TotpError(String),
+
+    #[display("Too many attempts, retry after {} seconds", _0)]
+    TooManyAttempts(i64),
 }
 
 impl ResponseError for AuthError {
@@ -42,6 +85,21 @@ impl ResponseError for AuthError {
             AuthError::UserNotFound => HttpResponse::NotFound().json("User not found"),
             AuthError::UserExists => HttpResponse::Conflict().json("User already exists"),
             AuthError::Unauthorized => HttpResponse::Unauthorized().finish(),
+            AuthError::InvalidRefreshToken | AuthError::RefreshTokenReused(_) => HttpResponse::Unauthorized().json("Invalid refresh token"),
+            AuthError::SessionNotFound => HttpResponse::NotFound().json("Session not found"),
+            AuthError::InvalidToken => HttpResponse::BadRequest().json("Invalid or expired token"),
+            AuthError::MailError(_) => HttpResponse::InternalServerError().json("Mail delivery failed"),
+            AuthError::InvalidEmail => HttpResponse::BadRequest().json("Invalid email"),
+            AuthError::EmailNotVerified => HttpResponse::Forbidden().json("Email not verified"),
+            AuthError::EmailInUse => HttpResponse::Conflict().json("Email already in use"),
+            AuthError::InvalidUserName(message) => HttpResponse::BadRequest().json(message),
+            AuthError::InvalidMfaCode => HttpResponse::Unauthorized().json("Invalid MFA code"),
+            AuthError::MfaAlreadyEnabled => HttpResponse::Conflict().json("MFA already enabled"),
+            AuthError::MfaNotEnabled => HttpResponse::BadRequest().json("MFA not enabled"),
+            AuthError::TotpError(_) => HttpResponse::InternalServerError().json("TOTP setup failed"),
+            AuthError::TooManyAttempts(retry_after) => HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .json("Too many attempts"),
         }
     }
 }
@@ -0,0 +1,264 @@
+use std::{collections::HashMap, env, fs, path::Path};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{errors::{Error as JwtError, ErrorKind}, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use ring::signature::{self, KeyPair};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::model::{Jwk, JwkSet};
+
+/// 签发的令牌种类, 用 typ 头和 aud 声明区分
+///
+/// 所有令牌使用同一组公开的密钥签名, 其他服务验签时必须检查 aud, 挑战令牌不能当作访问令牌
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Access,
+    MfaChallenge,
+}
+
+impl TokenKind {
+    pub fn typ(self) -> &'static str {
+        match self {
+            TokenKind::Access => "at+jwt",
+            TokenKind::MfaChallenge => "mfa-challenge+jwt",
+        }
+    }
+
+    pub fn audience(self) -> &'static str {
+        match self {
+            TokenKind::Access => "notes-api",
+            TokenKind::MfaChallenge => "notes-mfa-challenge",
+        }
+    }
+}
+
+/// JWT 签名密钥
+///
+/// 设置 `JWT_KEY_DIR` 时从目录加载 RS256 / EdDSA 私钥, 文件名 (不含 `.pem`) 作为 kid.
+/// 目录中的所有密钥都可以验签, 用 `JWT_ACTIVE_KID` 指定的密钥签发, 未指定时取 kid 最大的密钥.
+/// 轮换时放入新密钥即可, 旧密钥在其签发的令牌过期后从目录删除.
+/// 未设置 `JWT_KEY_DIR` 时退回到 `JWT_SECRET` 的 HS256 签名.
+pub struct KeyStore {
+    signing_kid: Option<String>,
+    signing_key: EncodingKey,
+    signing_algorithm: Algorithm,
+    verifying_keys: HashMap<String, (Algorithm, DecodingKey)>,
+    // 不带 kid 的 HS256 令牌
+    secret_key: Option<DecodingKey>,
+    jwks: JwkSet,
+}
+
+struct LoadedKey {
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    jwk: Jwk,
+}
+
+impl KeyStore {
+    pub fn from_env() -> Self {
+        let jwt_secret = env::var("JWT_SECRET").ok();
+        let Ok(key_dir) = env::var("JWT_KEY_DIR") else {
+            return Self::from_secret(&jwt_secret.expect("JWT_SECRET must be set"));
+        };
+
+        let mut keys = load_keys(Path::new(&key_dir));
+        assert!(!keys.is_empty(), "No signing keys found in {}", key_dir);
+
+        let signing_kid = env::var("JWT_ACTIVE_KID")
+            .unwrap_or_else(|_| keys.keys().max().cloned().expect("No signing keys"));
+        let signing = keys.remove(&signing_kid)
+            .unwrap_or_else(|| panic!("Active signing key {} not found", signing_kid));
+
+        let mut jwks = JwkSet { keys: vec![signing.jwk] };
+        let mut verifying_keys = HashMap::from([(signing_kid.clone(), (signing.algorithm, signing.decoding_key))]);
+        let mut keys: Vec<_> = keys.into_iter().collect();
+        keys.sort_by(|a, b| b.0.cmp(&a.0));
+        for (kid, key) in keys {
+            jwks.keys.push(key.jwk);
+            verifying_keys.insert(kid, (key.algorithm, key.decoding_key));
+        }
+        tracing::info!(kid = %signing_kid, keys = verifying_keys.len(), "Loaded JWT signing keys");
+
+        Self {
+            signing_kid: Some(signing_kid),
+            signing_key: signing.encoding_key,
+            signing_algorithm: signing.algorithm,
+            verifying_keys,
+            // 保留旧密钥, 切换期间签发的 HS256 令牌仍可验证
+            secret_key: jwt_secret.map(|secret| DecodingKey::from_secret(secret.as_ref())),
+            jwks,
+        }
+    }
+
+    // HS256 共享密钥, 不发布 JWKS
+    fn from_secret(secret: &str) -> Self {
+        Self {
+            signing_kid: None,
+            signing_key: EncodingKey::from_secret(secret.as_ref()),
+            signing_algorithm: Algorithm::HS256,
+            verifying_keys: HashMap::new(),
+            secret_key: Some(DecodingKey::from_secret(secret.as_ref())),
+            jwks: JwkSet { keys: Vec::new() },
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, kind: TokenKind, claims: &T) -> Result<String, JwtError> {
+        let mut header = Header::new(self.signing_algorithm);
+        header.kid = self.signing_kid.clone();
+        header.typ = Some(kind.typ().to_string());
+        jsonwebtoken::encode(&header, claims, &self.signing_key)
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, kind: TokenKind, token: &str) -> Result<T, JwtError> {
+        // 按 kid 选择验签密钥, 算法以密钥为准而不是令牌头
+        let header = jsonwebtoken::decode_header(token)?;
+        if header.typ.as_deref() != Some(kind.typ()) {
+            return Err(ErrorKind::InvalidToken.into());
+        }
+        let (algorithm, key) = match header.kid {
+            Some(kid) => self.verifying_keys.get(&kid)
+                .map(|(algorithm, key)| (*algorithm, key))
+                .ok_or(ErrorKind::InvalidToken)?,
+            None => self.secret_key.as_ref()
+                .map(|key| (Algorithm::HS256, key))
+                .ok_or(ErrorKind::InvalidToken)?,
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.set_audience(&[kind.audience()]);
+        Ok(jsonwebtoken::decode::<T>(token, key, &validation)?.claims)
+    }
+
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+}
+
+fn load_keys(key_dir: &Path) -> HashMap<String, LoadedKey> {
+    let entries = fs::read_dir(key_dir)
+        .unwrap_or_else(|e| panic!("Failed to read key directory {}: {}", key_dir.display(), e));
+
+    let mut keys = HashMap::new();
+    for entry in entries {
+        let path = entry.expect("Failed to read key directory entry").path();
+        if path.extension().is_none_or(|ext| ext != "pem") {
+            continue;
+        }
+        let kid = path.file_stem().and_then(|stem| stem.to_str())
+            .expect("Key file name must be valid UTF-8")
+            .to_string();
+        let pem = fs::read(&path)
+            .unwrap_or_else(|e| panic!("Failed to read key {}: {}", path.display(), e));
+        let key = load_key(&kid, &pem)
+            .unwrap_or_else(|e| panic!("Failed to load key {}: {}", path.display(), e));
+        keys.insert(kid, key);
+    }
+    keys
+}
+
+// 支持 PKCS#8 的 RSA / Ed25519 私钥和 PKCS#1 的 RSA 私钥
+fn load_key(kid: &str, pem: &[u8]) -> Result<LoadedKey, String> {
+    let parsed = pem::parse(pem).map_err(|e| e.to_string())?;
+    let der = parsed.contents();
+
+    if parsed.tag() == "PRIVATE KEY"
+        && let Ok(key_pair) = signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(der)
+    {
+        let x = URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref());
+        return Ok(LoadedKey {
+            algorithm: Algorithm::EdDSA,
+            encoding_key: EncodingKey::from_ed_pem(pem).map_err(|e| e.to_string())?,
+            decoding_key: DecodingKey::from_ed_components(&x).map_err(|e| e.to_string())?,
+            jwk: Jwk {
+                kty: "OKP".to_string(),
+                kid: kid.to_string(),
+                key_use: "sig".to_string(),
+                alg: "EdDSA".to_string(),
+                n: None,
+                e: None,
+                crv: Some("Ed25519".to_string()),
+                x: Some(x),
+            },
+        });
+    }
+
+    let key_pair = match parsed.tag() {
+        "PRIVATE KEY" => signature::RsaKeyPair::from_pkcs8(der),
+        "RSA PRIVATE KEY" => signature::RsaKeyPair::from_der(der),
+        tag => return Err(format!("unsupported key type {}", tag)),
+    }
+    .map_err(|e| e.to_string())?;
+
+    let components = signature::RsaPublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+    let n = URL_SAFE_NO_PAD.encode(&components.n);
+    let e = URL_SAFE_NO_PAD.encode(&components.e);
+    Ok(LoadedKey {
+        algorithm: Algorithm::RS256,
+        encoding_key: EncodingKey::from_rsa_pem(pem).map_err(|e| e.to_string())?,
+        decoding_key: DecodingKey::from_rsa_components(&n, &e).map_err(|e| e.to_string())?,
+        jwk: Jwk {
+            kty: "RSA".to_string(),
+            kid: kid.to_string(),
+            key_use: "sig".to_string(),
+            alg: "RS256".to_string(),
+            n: Some(n),
+            e: Some(e),
+            crv: None,
+            x: None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct TestClaims {
+        sub: String,
+        aud: String,
+        exp: i64,
+    }
+
+    fn claims(kind: TokenKind) -> TestClaims {
+        TestClaims {
+            sub: "user-1".to_string(),
+            aud: kind.audience().to_string(),
+            exp: chrono::Utc::now().timestamp() + 600,
+        }
+    }
+
+    #[test]
+    fn decodes_token_of_the_same_kind() {
+        let keys = KeyStore::from_secret("secret");
+        let token = keys.encode(TokenKind::Access, &claims(TokenKind::Access)).unwrap();
+        let decoded = keys.decode::<TestClaims>(TokenKind::Access, &token).unwrap();
+        assert_eq!(decoded.sub, "user-1");
+    }
+
+    #[test]
+    fn rejects_token_with_another_typ() {
+        let keys = KeyStore::from_secret("secret");
+        let token = keys.encode(TokenKind::MfaChallenge, &claims(TokenKind::MfaChallenge)).unwrap();
+        let err = keys.decode::<TestClaims>(TokenKind::Access, &token).unwrap_err();
+        assert_eq!(*err.kind(), ErrorKind::InvalidToken);
+    }
+
+    #[test]
+    fn rejects_token_with_another_audience() {
+        // typ 正确但 aud 属于挑战令牌, 模拟其他签发方只伪造了头部
+        let keys = KeyStore::from_secret("secret");
+        let token = keys.encode(TokenKind::Access, &claims(TokenKind::MfaChallenge)).unwrap();
+        let err = keys.decode::<TestClaims>(TokenKind::Access, &token).unwrap_err();
+        assert_eq!(*err.kind(), ErrorKind::InvalidAudience);
+    }
+
+    #[test]
+    fn rejects_token_signed_with_another_secret() {
+        let token = KeyStore::from_secret("other").encode(TokenKind::Access, &claims(TokenKind::Access)).unwrap();
+        let err = KeyStore::from_secret("secret").decode::<TestClaims>(TokenKind::Access, &token).unwrap_err();
+        assert_eq!(*err.kind(), ErrorKind::InvalidSignature);
+    }
+}
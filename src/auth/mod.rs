@@ -1,3 +1,4 @@
 pub mod model;
 pub mod service;
-pub mod error;
\ No newline at end of file
+pub mod error;
+pub mod keys;
\ No newline at end of file
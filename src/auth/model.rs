@@ -7,22 +7,58 @@ pub struct Claims {
     pub sub: String,    // 用户ID
     pub exp: usize,     // 过期时间
     pub iat: usize,     // 签发时间
+    pub sid: String,    // 会话ID
+    pub jti: String,    // 令牌ID
+    pub aud: String,    // 受众, 区分挑战令牌
 }
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
-pub struct User {
+/// users 表的记录, 包含密码哈希等内部字段
+///
+/// `*Row` 类型只用于读取数据库, 不实现 `Serialize`, 接口返回 [`UserProfile`]
+#[derive(Debug, FromRow)]
+pub struct UserRow {
     pub id: String,
     pub user_name: String,
     pub email: String,
     pub password_hash: String,
+    pub email_verified: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
+}
+
+/// 对外返回的用户资料
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub id: String,
+    pub user_name: String,
+    pub email: String,
+    pub email_verified: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+impl From<UserRow> for UserProfile {
+    fn from(user: UserRow) -> Self {
+        Self {
+            id: user.id,
+            user_name: user.user_name,
+            email: user.email,
+            email_verified: user.email_verified,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
+    #[serde(default)]
+    pub device_id: Option<String>,
+    #[serde(default)]
+    pub device_name: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -30,18 +66,199 @@ pub struct RegisterRequest {
     pub name: String,
     pub email: String,
     pub password: String,
+    #[serde(default)]
+    pub device_id: Option<String>,
+    #[serde(default)]
+    pub device_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthResponse {
-    pub token: String,
+    pub token: String,          // 访问令牌
+    pub expires_in: i64,        // 访问令牌有效期 (秒)
+    pub refresh_token: String,
     pub user_id: String,
     pub user_name: String,
 }
 
-/// 黑名单令牌
-#[derive(Debug, Serialize, Deserialize, FromRow)]
-pub struct BlacklistedToken {
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordChangeRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileUpdateRequest {
+    pub user_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailChangeRequest {
+    pub new_email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailVerifyRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordResetRequest {
     pub token: String,
-    pub expires_at: DateTime<Utc>,
-}
\ No newline at end of file
+    pub new_password: String,
+}
+
+/// 两步验证挑战令牌的声明, 不能作为访问令牌使用
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaChallengeClaims {
+    pub sub: String,    // 用户ID
+    pub exp: usize,     // 过期时间
+    pub iat: usize,     // 签发时间
+    pub aud: String,    // 受众, 区分访问令牌
+}
+
+/// 密码验证通过后的结果
+#[derive(Debug)]
+pub enum AuthOutcome {
+    Authenticated(UserRow),
+    // 已开启两步验证, 需要继续提交验证码
+    MfaRequired(MfaChallenge),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaChallenge {
+    pub mfa_required: bool,
+    pub challenge_token: String,
+    pub expires_in: i64,        // 挑战令牌有效期 (秒)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaVerifyRequest {
+    pub challenge_token: String,
+    pub code: String,           // TOTP 验证码或恢复码
+    #[serde(default)]
+    pub device_id: Option<String>,
+    #[serde(default)]
+    pub device_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaDisableRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountDeleteRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountDeletion {
+    pub deletion_scheduled_at: DateTime<Utc>,
+}
+
+/// 导出数据中的账号资料
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountProfile {
+    #[serde(flatten)]
+    pub user: UserProfile,
+    pub mfa_enabled: bool,
+    pub sessions: Vec<Session>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpEnrollment {
+    pub secret: String,         // base32 编码
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+/// 用户的两步验证设置
+#[derive(Debug, FromRow)]
+pub struct UserMfaRow {
+    pub user_id: String,
+    pub totp_secret: Vec<u8>,
+    pub last_used_step: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub enabled_at: Option<DateTime<Utc>>,
+}
+
+/// 一次性用户令牌的用途
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum TokenPurpose {
+    PasswordReset,
+    EmailVerification,
+    EmailChange,
+}
+
+/// 创建会话时记录的设备信息
+#[derive(Debug, Default)]
+pub struct SessionDevice {
+    pub device_id: Option<String>,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// 登录会话
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Session {
+    pub id: String,
+    pub device_id: Option<String>,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub current: bool,      // 是否为当前请求所在的会话
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevokeSessionsResponse {
+    pub revoked_sessions: u64,
+}
+
+/// 公开的验签公钥集合 (RFC 7517)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub kid: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+    pub alg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,      // RSA 模数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,      // RSA 指数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,    // EdDSA 曲线
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,      // EdDSA 公钥
+}
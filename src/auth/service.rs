@@ -1,62 +1,205 @@
 use argon2::{
     password_hash::{
-        rand_core::OsRng,
+        rand_core::{OsRng, RngCore},
         PasswordHash, PasswordHasher, PasswordVerifier, SaltString
     },
     Argon2
 };
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
-use std::env;
-use super::error::AuthError;
-use crate::database::{AuthDatabase, Database};
-use super::model::{User, RegisterRequest, Claims};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use moka::sync::Cache;
+use sha2::{Digest, Sha256};
+use std::{env, net::IpAddr, time::Duration};
+use super::{error::AuthError, keys::{KeyStore, TokenKind}};
+use crate::{database::{AuthDatabase, Database}, utils::{config::env_or, mailer::{mailer_from_env, Email, Mailer}}};
+use totp_rs::{Algorithm, TOTP};
+use crate::log_error;
+use super::model::{AccountDeletion, AccountProfile, AuthOutcome, AuthResponse, UserRow, RegisterRequest, Claims, JwkSet, MfaChallenge, MfaChallengeClaims, ProfileUpdateRequest, RecoveryCodes, Session, SessionDevice, TokenPurpose, TotpEnrollment, UserProfile};
+
+// 用户名最大长度
+const USER_NAME_MAX_CHARS: usize = 50;
+// 会话状态缓存容量
+const SESSION_CACHE_CAPACITY: u64 = 10_000;
+// 邮箱验证令牌有效期 (小时)
+const VERIFICATION_EXPIRY_HOURS: i64 = 48;
+// 两步验证挑战令牌有效期 (分钟)
+const MFA_CHALLENGE_EXPIRY_MINUTES: i64 = 5;
+// TOTP 参数, 与常见验证器应用的默认值一致
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+// 有效会话的缓存时间, 其他实例吊销的会话最多在该时间后失效
+const ACTIVE_SESSION_TTL: Duration = Duration::from_secs(60);
+
+/// 未验证邮箱账号的限制策略
+#[derive(Debug, Clone, Copy)]
+enum UnverifiedPolicy {
+    Allow,
+    // 注册超过宽限期 (小时) 后禁止登录和刷新令牌
+    Block(i64),
+}
+
+/// 登录失败限制
+#[derive(Debug, Clone, Copy)]
+struct LoginThrottle {
+    account_threshold: i32,     // 每个账号允许的连续失败次数
+    ip_threshold: i32,          // 每个 IP 允许的连续失败次数
+    base_lockout: i64,          // 秒
+    max_lockout: i64,           // 秒
+    window: i64,                // 秒, 超过该时间没有失败则重新计数
+    trust_proxy_headers: bool,  // 部署在可信代理后时从转发头获取客户端 IP
+}
+
+impl LoginThrottle {
+    // 达到阈值时锁定基础时长, 之后每次失败翻倍, 不超过最大锁定时长
+    fn lockout(&self, failures: i32, threshold: i32) -> i64 {
+        let exponent = (failures - threshold).clamp(0, 20) as u32;
+        self.base_lockout.saturating_mul(1 << exponent).min(self.max_lockout)
+    }
+}
 
 pub struct AuthService {
-    jwt_secret: String,
-    jwt_expiry: i64,    // 小时
+    keys: KeyStore,
+    jwt_expiry: i64,    // 分钟
+    refresh_expiry: i64,    // 天
+    reset_expiry: i64,  // 分钟
+    deletion_grace: i64,    // 天
+    unverified_policy: UnverifiedPolicy,
+    totp_issuer: String,
+    throttle: LoginThrottle,
     db: Database,
+    mailer: Box<dyn Mailer>,
+    active_sessions: Cache<String, ()>,
+    // 已吊销的会话只需保留到其访问令牌过期
+    revoked_sessions: Cache<String, ()>,
+    // 邮箱不存在时也校验一次密码, 使响应时间与密码错误一致
+    dummy_password_hash: String,
 }
 
 impl AuthService {
     pub fn new(db: Database) -> Self {
-        let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-        let jwt_expiry = env::var("JWT_EXPIRY_HOURS")
-            .unwrap_or_else(|_| "24".to_string())
-            .parse()
-            .unwrap_or(24);
+        let keys = KeyStore::from_env();
+        let jwt_expiry = env_or("JWT_EXPIRY_MINUTES", 15);
+        let refresh_expiry = env_or("REFRESH_TOKEN_EXPIRY_DAYS", 30);
+        let reset_expiry = env_or("PASSWORD_RESET_EXPIRY_MINUTES", 30);
+        let deletion_grace = env_or("ACCOUNT_DELETION_GRACE_DAYS", 14);
+        let unverified_policy = match env::var("UNVERIFIED_ACCOUNT_POLICY").as_deref() {
+            Ok("block") => UnverifiedPolicy::Block(env_or("UNVERIFIED_GRACE_HOURS", 0)),
+            _ => UnverifiedPolicy::Allow,
+        };
+
+        let totp_issuer = env_or("TOTP_ISSUER", "Notes".to_string());
+        let throttle = LoginThrottle {
+            account_threshold: env_or("LOGIN_MAX_ATTEMPTS", 5),
+            ip_threshold: env_or("LOGIN_IP_MAX_ATTEMPTS", 20),
+            base_lockout: env_or("LOGIN_LOCKOUT_SECONDS", 60),
+            max_lockout: env_or("LOGIN_MAX_LOCKOUT_SECONDS", 3600),
+            window: env_or::<i64>("LOGIN_ATTEMPT_WINDOW_MINUTES", 15) * 60,
+            trust_proxy_headers: env_or("TRUST_PROXY_HEADERS", false),
+        };
+
+        let active_sessions = Cache::builder()
+            .max_capacity(SESSION_CACHE_CAPACITY)
+            .time_to_live(ACTIVE_SESSION_TTL)
+            .build();
+        let revoked_sessions = Cache::builder()
+            .max_capacity(SESSION_CACHE_CAPACITY)
+            .time_to_live(Duration::from_secs(jwt_expiry.max(1) as u64 * 60))
+            .build();
+
+        let dummy_password_hash = Argon2::default()
+            .hash_password(generate_random_token().as_bytes(), &SaltString::generate(&mut OsRng))
+            .expect("Failed to hash dummy password")
+            .to_string();
 
-        Self { jwt_secret, jwt_expiry, db }
+        Self { keys, jwt_expiry, refresh_expiry, reset_expiry, deletion_grace, unverified_policy, totp_issuer, throttle, db, mailer: mailer_from_env(), active_sessions, revoked_sessions, dummy_password_hash }
     }
 
     // 注册用户
-    pub async fn register_user(&self, request: RegisterRequest) -> Result<User, AuthError> {
+    pub async fn register_user(&self, request: RegisterRequest) -> Result<UserRow, AuthError> {
+        let email = normalize_email(&request.email)?;
+
         // 检查用户是否已经存在
-        if let Ok(_) = self.db.get_user_by_email(&request.email).await {
+        if self.db.get_user_by_email(&email).await.is_ok() {
             return Err(AuthError::UserExists);
         }
 
-        let name = if request.name.chars().count() > 10 {
-            request.name.chars().take(10).collect::<String>()
-        } else {
-            request.name.clone()
-        };
-        
+        let name = validate_user_name(&request.name)?;
         let password_hash = self.hash_password(&request.password)?;
         let user_id = uuid::Uuid::new_v4().to_string();
 
-        self.db.insert_user(&user_id, &name, &request.email, &password_hash).await
+        let user = self.db.insert_user(&user_id, &name, &email, &password_hash).await?;
+
+        // 验证邮件发送失败不影响注册, 用户可以重新发送
+        if let Err(e) = self.send_verification_email(&user).await {
+            tracing::error!(error = %e, user_id = %user.id, "Failed to send verification email");
+        }
+
+        Ok(user)
     }
 
-    // 用户认证
-    pub async fn authenticate(&self, email: &str, password: &str) -> Result<User, AuthError> {
-        let user = self.db.get_user_by_email(email).await?;
+    // 用户认证, 开启两步验证时返回挑战令牌
+    pub async fn authenticate(&self, email: &str, password: &str, ip: Option<&str>) -> Result<AuthOutcome, AuthError> {
+        let mut throttle_keys = vec![(format!("email:{}", email.trim().to_lowercase()), self.throttle.account_threshold)];
+        if let Some(ip) = ip {
+            throttle_keys.push((format!("ip:{}", ip), self.throttle.ip_threshold));
+        }
+        self.check_login_lock(&throttle_keys).await?;
 
-        self.verify_password(&user.password_hash, password)?;
+        let user = match self.db.get_user_by_email(email.trim()).await {
+            Ok(user) => user,
+            Err(AuthError::UserNotFound) => {
+                // 与密码错误返回相同的错误并消耗相同的时间, 避免枚举已注册邮箱
+                let _ = self.verify_password(&self.dummy_password_hash, password);
+                self.record_login_failure(&throttle_keys).await?;
+                return Err(AuthError::InvalidCredentials);
+            }
+            Err(e) => return Err(e),
+        };
 
-        Ok(user)
+        if let Err(e) = self.verify_password(&user.password_hash, password) {
+            if matches!(e, AuthError::InvalidCredentials) {
+                self.record_login_failure(&throttle_keys).await?;
+            }
+            return Err(e);
+        }
+        // 只清除账号的失败计数, IP 计数按时间窗口自然过期
+        self.db.clear_login_failures(&throttle_keys[0].0).await?;
+
+        match self.db.get_user_mfa(&user.id).await? {
+            Some(mfa) if mfa.enabled_at.is_some() => Ok(AuthOutcome::MfaRequired(self.generate_mfa_challenge(&user.id)?)),
+            _ => Ok(AuthOutcome::Authenticated(user)),
+        }
     }
 
-    pub async fn get_user_by_id(&self, user_id: &str) -> Result<User, AuthError> {
+    async fn check_login_lock(&self, keys: &[(String, i32)]) -> Result<(), AuthError> {
+        let keys: Vec<String> = keys.iter().map(|(key, _)| key.clone()).collect();
+        if let Some(locked_until) = self.db.get_login_lock(&keys).await? {
+            let retry_after = (locked_until - chrono::Utc::now()).num_seconds().max(1);
+            return Err(AuthError::TooManyAttempts(retry_after));
+        }
+        Ok(())
+    }
+
+    // 失败次数达到阈值后锁定
+    async fn record_login_failure(&self, keys: &[(String, i32)]) -> Result<(), AuthError> {
+        let now = chrono::Utc::now();
+        let window_start = now - chrono::Duration::seconds(self.throttle.window);
+
+        for (key, threshold) in keys {
+            let failures = self.db.record_login_failure(key, window_start).await?;
+            if failures < *threshold {
+                continue;
+            }
+
+            let lockout = self.throttle.lockout(failures, *threshold);
+            let locked_until = now + chrono::Duration::seconds(lockout);
+            self.db.lock_login(key, locked_until).await?;
+            tracing::warn!(target: "audit", key = %key, failures, lockout, locked_until = %locked_until, "Login locked after repeated failures");
+        }
+        Ok(())
+    }
+
+    pub async fn get_user_by_id(&self, user_id: &str) -> Result<UserRow, AuthError> {
         self.db.get_user_by_id(user_id).await
     }
 
@@ -71,54 +214,578 @@ impl AuthService {
     pub fn verify_password(&self, hash: &str, password: &str) -> Result<(), AuthError> {
         let argon2 = Argon2::default();
         let hash = PasswordHash::new(hash)?;
-        if let Err(_) = argon2.verify_password(password.as_bytes(), &hash) {
+        if argon2.verify_password(password.as_bytes(), &hash).is_err() {
             Err(AuthError::InvalidCredentials)
         } else {
             Ok(())
         }
     }
 
-    pub fn generate_token(&self, user_id: &str) -> Result<String, AuthError> {
+    pub fn generate_token(&self, user_id: &str, session_id: &str) -> Result<String, AuthError> {
         let now = chrono::Utc::now();
-        let exp = now + chrono::Duration::hours(self.jwt_expiry);
+        let exp = now + chrono::Duration::minutes(self.jwt_expiry);
 
         let claims = Claims {
             sub: user_id.to_owned(),
             exp: exp.timestamp() as usize,
             iat: now.timestamp() as usize,
+            sid: session_id.to_owned(),
+            jti: uuid::Uuid::new_v4().to_string(),
+            aud: TokenKind::Access.audience().to_string(),
         };
 
-        Ok(jsonwebtoken::encode(
-            &Header::default(), 
-            &claims, 
-            &EncodingKey::from_secret(self.jwt_secret.as_ref())
-        )?)
+        Ok(self.keys.encode(TokenKind::Access, &claims)?)
+    }
+
+    // 登录或注册成功后创建会话, 签发访问令牌和刷新令牌
+    pub async fn issue_tokens(&self, user: &UserRow, mut device: SessionDevice) -> Result<AuthResponse, AuthError> {
+        self.check_email_verified(user)?;
+
+        // 宽限期内重新登录即取消注销
+        if user.deletion_scheduled_at.is_some() {
+            self.db.cancel_user_deletion(&user.id).await?;
+            tracing::warn!(target: "audit", user_id = %user.id, "Account deletion cancelled by sign in");
+        }
+
+        // 按列长度截断客户端提供的设备信息
+        device.device_id = device.device_id.map(|id| id.chars().take(64).collect());
+        device.device_name = device.device_name.map(|name| name.chars().take(100).collect());
+        // 只保存能解析的 IP 地址, 规范化后不超过列长度
+        device.ip = device.ip.and_then(|ip| ip.parse::<IpAddr>().ok()).map(|ip| ip.to_string());
+
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let token = self.generate_token(&user.id, &session_id)?;
+        let refresh_token = generate_random_token();
+        let expires_at = chrono::Utc::now() + chrono::Duration::days(self.refresh_expiry);
+        self.db.create_session(&user.id, &session_id, &device, &hash_token(&refresh_token), expires_at).await?;
+
+        Ok(AuthResponse {
+            token,
+            expires_in: self.jwt_expiry * 60,
+            refresh_token,
+            user_id: user.id.clone(),
+            user_name: user.user_name.clone(),
+        })
+    }
+
+    // 轮换刷新令牌, 旧令牌只能使用一次
+    pub async fn refresh(&self, refresh_token: &str) -> Result<AuthResponse, AuthError> {
+        let new_refresh_token = generate_random_token();
+        let expires_at = chrono::Utc::now() + chrono::Duration::days(self.refresh_expiry);
+        let (user_id, session_id) = match self.db.rotate_refresh_token(
+            &hash_token(refresh_token),
+            &hash_token(&new_refresh_token),
+            expires_at,
+        ).await {
+            Ok(rotated) => rotated,
+            Err(AuthError::RefreshTokenReused(session_id)) => {
+                // 数据库中已吊销, 同步让该会话的访问令牌立即失效
+                self.mark_session_revoked(&session_id);
+                return Err(AuthError::RefreshTokenReused(session_id));
+            }
+            Err(e) => return Err(e),
+        };
+        let user = self.db.get_user_by_id(&user_id).await?;
+        self.check_email_verified(&user)?;
+
+        Ok(AuthResponse {
+            token: self.generate_token(&user.id, &session_id)?,
+            expires_in: self.jwt_expiry * 60,
+            refresh_token: new_refresh_token,
+            user_id: user.id,
+            user_name: user.user_name,
+        })
     }
 
     pub async fn validate_token(&self, token: &str) -> Result<Claims, AuthError> {
-        // 先检查黑名单
-        if self.db.is_token_blacklisted(token).await? {
-            println!("Token is blacklisted");
+        let claims = self.keys.decode::<Claims>(TokenKind::Access, token)?;
+
+        // 会话被吊销后其访问令牌立即失效
+        if self.revoked_sessions.contains_key(&claims.sid) {
+            tracing::debug!(session_id = %claims.sid, "Session is revoked");
             return Err(AuthError::Unauthorized);
         }
-        
-        Ok(jsonwebtoken::decode::<Claims>(
-            token, 
-            &DecodingKey::from_secret(self.jwt_secret.as_ref()), 
-            &Validation::default(),
-        )?.claims)
+
+        // 缓存未命中时才查询数据库
+        if !self.active_sessions.contains_key(&claims.sid) {
+            if !self.db.touch_session(&claims.sub, &claims.sid).await? {
+                tracing::debug!(session_id = %claims.sid, "Session is revoked");
+                self.revoked_sessions.insert(claims.sid.clone(), ());
+                return Err(AuthError::Unauthorized);
+            }
+            self.active_sessions.insert(claims.sid.clone(), ());
+        }
+
+        Ok(claims)
+    }
+
+    // 客户端可以伪造转发头, 只有部署在可信代理后才能使用
+    pub fn trusts_proxy_headers(&self) -> bool {
+        self.throttle.trust_proxy_headers
+    }
+
+    // 供其他服务验签的公钥
+    pub fn jwks(&self) -> &JwkSet {
+        self.keys.jwks()
+    }
+
+    pub async fn list_sessions(&self, user_id: &str, current_session_id: &str) -> Result<Vec<Session>, AuthError> {
+        self.db.list_sessions(user_id, current_session_id).await
+    }
+
+    pub async fn revoke_session(&self, user_id: &str, session_id: &str) -> Result<(), AuthError> {
+        if !self.db.revoke_session(user_id, session_id).await? {
+            return Err(AuthError::SessionNotFound);
+        }
+        self.mark_session_revoked(session_id);
+        Ok(())
+    }
+
+    // 吊销当前会话以外的所有会话
+    pub async fn revoke_other_sessions(&self, user_id: &str, current_session_id: &str) -> Result<u64, AuthError> {
+        let session_ids = self.db.revoke_other_sessions(user_id, current_session_id).await?;
+        for session_id in &session_ids {
+            self.mark_session_revoked(session_id);
+        }
+        Ok(session_ids.len() as u64)
     }
 
-    pub async fn logout(&self, token: &str) -> Result<(), AuthError> {
-        let expires_at = chrono::Utc::now() + chrono::Duration::hours(self.jwt_expiry);
-        Ok(self.db.logout_user(token, expires_at).await?)
+    // 退出登录时吊销当前会话及其刷新令牌
+    pub async fn logout(&self, user_id: &str, session_id: &str) -> Result<(), AuthError> {
+        self.db.revoke_session(user_id, session_id).await?;
+        self.mark_session_revoked(session_id);
+        Ok(())
+    }
+
+    // 修改密码后吊销其他会话
+    pub async fn change_password(&self, user_id: &str, session_id: &str, current_password: &str, new_password: &str) -> Result<(), AuthError> {
+        let user = self.db.get_user_by_id(user_id).await?;
+        self.verify_password(&user.password_hash, current_password)?;
+
+        let password_hash = self.hash_password(new_password)?;
+        for session_id in self.db.update_password(user_id, &password_hash, Some(session_id)).await? {
+            self.mark_session_revoked(&session_id);
+        }
+        Ok(())
+    }
+
+    pub async fn get_profile(&self, user_id: &str) -> Result<UserProfile, AuthError> {
+        Ok(self.db.get_user_by_id(user_id).await?.into())
+    }
+
+    pub async fn update_profile(&self, user_id: &str, request: ProfileUpdateRequest) -> Result<UserProfile, AuthError> {
+        let user = match request.user_name {
+            Some(user_name) => self.db.update_user_name(user_id, &validate_user_name(&user_name)?).await?,
+            None => self.db.get_user_by_id(user_id).await?,
+        };
+        Ok(user.into())
+    }
+
+    // 修改邮箱需要重新输入密码, 向新邮箱发送确认令牌, 确认前仍使用旧邮箱
+    pub async fn request_email_change(&self, user_id: &str, new_email: &str, password: &str) -> Result<(), AuthError> {
+        let user = self.db.get_user_by_id(user_id).await?;
+        self.verify_password(&user.password_hash, password)?;
+
+        let new_email = normalize_email(new_email)?;
+        match self.db.get_user_by_email(&new_email).await {
+            Ok(_) => return Err(AuthError::EmailInUse),
+            Err(AuthError::UserNotFound) => {}
+            Err(e) => return Err(e),
+        }
+
+        let token = generate_random_token();
+        let expires_at = chrono::Utc::now() + chrono::Duration::hours(VERIFICATION_EXPIRY_HOURS);
+        self.db.create_email_change_token(user_id, &new_email, &hash_token(&token), expires_at).await?;
+
+        self.mailer.send(&Email {
+            to: new_email,
+            subject: "Confirm your new email".to_string(),
+            body: format!(
+                "Use this token to confirm your new email within {} hours:\n\n{}",
+                VERIFICATION_EXPIRY_HOURS, token,
+            ),
+        }).map_err(AuthError::MailError)
+    }
+
+    // 确认新邮箱, 并通知旧邮箱
+    pub async fn confirm_email_change(&self, token: &str) -> Result<(), AuthError> {
+        let (user, new_email) = self.db.change_email(&hash_token(token)).await?;
+        tracing::warn!(target: "audit", user_id = %user.id, "Account email changed");
+
+        let notice = Email {
+            to: user.email,
+            subject: "Your email was changed".to_string(),
+            body: format!("The email of your account was changed to {}.", new_email),
+        };
+        if let Err(e) = self.mailer.send(&notice) {
+            tracing::error!(error = %e, user_id = %user.id, "Failed to send email change notice");
+        }
+        Ok(())
+    }
+
+    // 注销账号需要重新输入密码, 吊销所有会话后在宽限期结束时删除
+    pub async fn schedule_account_deletion(&self, user_id: &str, password: &str) -> Result<AccountDeletion, AuthError> {
+        let user = self.db.get_user_by_id(user_id).await?;
+        self.verify_password(&user.password_hash, password)?;
+
+        let deletion_scheduled_at = chrono::Utc::now() + chrono::Duration::days(self.deletion_grace);
+        for session_id in self.db.schedule_user_deletion(user_id, deletion_scheduled_at).await? {
+            self.mark_session_revoked(&session_id);
+        }
+        tracing::warn!(target: "audit", user_id = %user_id, %deletion_scheduled_at, "Account deletion scheduled");
+
+        if self.deletion_grace <= 0 {
+            self.db.delete_user(user_id).await?;
+            tracing::warn!(target: "audit", user_id = %user_id, "Account deleted");
+            return Ok(AccountDeletion { deletion_scheduled_at });
+        }
+
+        // 通知邮件发送失败不影响注销
+        let notice = Email {
+            to: user.email,
+            subject: "Your account will be deleted".to_string(),
+            body: format!(
+                "Your account and all notes will be permanently deleted on {}.\n\nSign in before then to cancel the deletion.",
+                deletion_scheduled_at.to_rfc2822(),
+            ),
+        };
+        if let Err(e) = self.mailer.send(&notice) {
+            tracing::error!(error = %e, user_id = %user_id, "Failed to send account deletion email");
+        }
+
+        Ok(AccountDeletion { deletion_scheduled_at })
+    }
+
+    // 用于数据导出的账号资料
+    pub async fn account_profile(&self, user_id: &str, current_session_id: &str) -> Result<AccountProfile, AuthError> {
+        let user = self.db.get_user_by_id(user_id).await?;
+        let mfa_enabled = self.db.get_user_mfa(user_id).await?
+            .is_some_and(|mfa| mfa.enabled_at.is_some());
+        let sessions = self.db.list_sessions(user_id, current_session_id).await?;
+
+        Ok(AccountProfile {
+            user: user.into(),
+            mfa_enabled,
+            sessions,
+        })
+    }
+
+    // 发送密码重置邮件, 邮箱未注册时同样返回成功, 避免泄露账号是否存在
+    pub async fn request_password_reset(&self, email: &str) -> Result<(), AuthError> {
+        let user = match self.db.get_user_by_email(email.trim()).await {
+            Ok(user) => user,
+            Err(AuthError::UserNotFound) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let token = generate_random_token();
+        let expires_at = chrono::Utc::now() + chrono::Duration::minutes(self.reset_expiry);
+        self.db.create_user_token(&user.id, TokenPurpose::PasswordReset, &hash_token(&token), expires_at).await?;
+
+        self.mailer.send(&Email {
+            to: user.email,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Use this token to reset your password within {} minutes:\n\n{}\n\nIf you did not request a password reset, you can ignore this email.",
+                self.reset_expiry, token,
+            ),
+        }).map_err(AuthError::MailError)
+    }
+
+    // 重置密码后吊销所有会话
+    pub async fn reset_password(&self, token: &str, new_password: &str) -> Result<(), AuthError> {
+        let user_id = self.db.consume_user_token(TokenPurpose::PasswordReset, &hash_token(token)).await?;
+
+        let password_hash = self.hash_password(new_password)?;
+        for session_id in self.db.update_password(&user_id, &password_hash, None).await? {
+            self.mark_session_revoked(&session_id);
+        }
+        Ok(())
+    }
+
+    pub async fn verify_email(&self, token: &str) -> Result<(), AuthError> {
+        let user_id = self.db.consume_user_token(TokenPurpose::EmailVerification, &hash_token(token)).await?;
+        self.db.set_email_verified(&user_id).await
+    }
+
+    // 重新发送验证邮件, 邮箱未注册或已验证时同样返回成功
+    pub async fn resend_verification_email(&self, email: &str) -> Result<(), AuthError> {
+        match self.db.get_user_by_email(email.trim()).await {
+            Ok(user) if !user.email_verified => self.send_verification_email(&user).await,
+            Ok(_) | Err(AuthError::UserNotFound) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn send_verification_email(&self, user: &UserRow) -> Result<(), AuthError> {
+        let token = generate_random_token();
+        let expires_at = chrono::Utc::now() + chrono::Duration::hours(VERIFICATION_EXPIRY_HOURS);
+        self.db.create_user_token(&user.id, TokenPurpose::EmailVerification, &hash_token(&token), expires_at).await?;
+
+        self.mailer.send(&Email {
+            to: user.email.clone(),
+            subject: "Verify your email".to_string(),
+            body: format!(
+                "Use this token to verify your email within {} hours:\n\n{}",
+                VERIFICATION_EXPIRY_HOURS, token,
+            ),
+        }).map_err(AuthError::MailError)
+    }
+
+    fn check_email_verified(&self, user: &UserRow) -> Result<(), AuthError> {
+        match self.unverified_policy {
+            UnverifiedPolicy::Block(grace) if !user.email_verified
+                && user.created_at + chrono::Duration::hours(grace) <= chrono::Utc::now() => Err(AuthError::EmailNotVerified),
+            _ => Ok(()),
+        }
+    }
+
+    fn generate_mfa_challenge(&self, user_id: &str) -> Result<MfaChallenge, AuthError> {
+        let now = chrono::Utc::now();
+        let exp = now + chrono::Duration::minutes(MFA_CHALLENGE_EXPIRY_MINUTES);
+
+        let claims = MfaChallengeClaims {
+            sub: user_id.to_owned(),
+            exp: exp.timestamp() as usize,
+            iat: now.timestamp() as usize,
+            aud: TokenKind::MfaChallenge.audience().to_string(),
+        };
+
+        Ok(MfaChallenge {
+            mfa_required: true,
+            challenge_token: self.keys.encode(TokenKind::MfaChallenge, &claims)?,
+            expires_in: MFA_CHALLENGE_EXPIRY_MINUTES * 60,
+        })
+    }
+
+    // 登录第二步, 校验挑战令牌和验证码
+    pub async fn verify_mfa_challenge(&self, challenge_token: &str, code: &str) -> Result<UserRow, AuthError> {
+        let claims = self.keys.decode::<MfaChallengeClaims>(TokenKind::MfaChallenge, challenge_token)?;
+
+        // 挑战令牌有效期内同样限制验证码的尝试次数
+        let throttle_keys = [(format!("user:{}", claims.sub), self.throttle.account_threshold)];
+        self.check_login_lock(&throttle_keys).await?;
+        match self.verify_mfa_code(&claims.sub, code).await {
+            Ok(_) => self.db.clear_login_failures(&throttle_keys[0].0).await?,
+            Err(AuthError::InvalidMfaCode) => {
+                self.record_login_failure(&throttle_keys).await?;
+                return Err(AuthError::InvalidMfaCode);
+            }
+            Err(e) => return Err(e),
+        }
+
+        self.db.get_user_by_id(&claims.sub).await
+    }
+
+    // 生成待确认的 TOTP 密钥
+    pub async fn enroll_totp(&self, user_id: &str) -> Result<TotpEnrollment, AuthError> {
+        let user = self.db.get_user_by_id(user_id).await?;
+
+        let mut secret = vec![0u8; 20];
+        OsRng.fill_bytes(&mut secret);
+        let totp = TOTP::new(Algorithm::SHA1, TOTP_DIGITS, 1, TOTP_STEP, secret, Some(self.totp_issuer.clone()), user.email)
+            .map_err(|e| AuthError::TotpError(e.to_string()))?;
+
+        if !self.db.save_pending_totp(user_id, &totp.secret).await? {
+            return Err(AuthError::MfaAlreadyEnabled);
+        }
+
+        Ok(TotpEnrollment {
+            secret: totp.get_secret_base32(),
+            otpauth_uri: totp.get_url(),
+        })
+    }
+
+    // 用验证码确认开启两步验证, 返回只显示一次的恢复码
+    pub async fn confirm_totp(&self, user_id: &str, code: &str) -> Result<RecoveryCodes, AuthError> {
+        let mfa = self.db.get_user_mfa(user_id).await?.ok_or(AuthError::MfaNotEnabled)?;
+        if mfa.enabled_at.is_some() {
+            return Err(AuthError::MfaAlreadyEnabled);
+        }
+
+        let step = matching_totp_step(&mfa.totp_secret, code).ok_or(AuthError::InvalidMfaCode)?;
+
+        let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect();
+        let code_hashes = recovery_codes.iter()
+            .map(|code| self.hash_password(code))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.db.enable_mfa(user_id, step as i64, &code_hashes).await?;
+
+        Ok(RecoveryCodes { recovery_codes })
+    }
+
+    // 关闭两步验证需要重新输入密码
+    pub async fn disable_mfa(&self, user_id: &str, password: &str) -> Result<(), AuthError> {
+        let user = self.db.get_user_by_id(user_id).await?;
+        self.verify_password(&user.password_hash, password)?;
+        self.db.delete_mfa(user_id).await
+    }
+
+    // 校验 TOTP 验证码或恢复码, 两者都只能使用一次
+    async fn verify_mfa_code(&self, user_id: &str, code: &str) -> Result<(), AuthError> {
+        let mfa = self.db.get_user_mfa(user_id).await?
+            .filter(|mfa| mfa.enabled_at.is_some())
+            .ok_or(AuthError::MfaNotEnabled)?;
+
+        if let Some(step) = matching_totp_step(&mfa.totp_secret, code) {
+            if self.db.use_totp_step(user_id, step as i64).await? {
+                return Ok(());
+            }
+            return Err(AuthError::InvalidMfaCode);
+        }
+
+        let code = code.trim().to_lowercase();
+        for (code_id, code_hash) in self.db.get_recovery_codes(user_id).await? {
+            if self.verify_password(&code_hash, &code).is_ok() && self.db.use_recovery_code(&code_id).await? {
+                return Ok(());
+            }
+        }
+        Err(AuthError::InvalidMfaCode)
+    }
+
+    fn mark_session_revoked(&self, session_id: &str) {
+        self.active_sessions.invalidate(session_id);
+        self.revoked_sessions.insert(session_id.to_string(), ());
     }
 }
 
+// 刷新令牌等不透明令牌为 32 字节随机数, 不含任何用户信息
+fn generate_random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+// 数据库只保存不透明令牌的哈希
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
 pub async fn cleanup_expired_tokens(auth_service: actix_web::web::Data<AuthService>) {
     // 每小时清理一次过期token
     loop {
         auth_service.db.cleanup_expired_tokens().await;
         tokio::time::sleep(std::time::Duration::from_secs(3600)).await;
     }
-}
\ No newline at end of file
+}
+
+pub async fn purge_deleted_accounts(auth_service: actix_web::web::Data<AuthService>) {
+    // 每小时删除一次宽限期已过的账号
+    loop {
+        match auth_service.db.delete_scheduled_users(chrono::Utc::now()).await {
+            Ok(deleted) => tracing::info!(deleted, "Purged deleted accounts"),
+            Err(e) => {
+                log_error!(e, "Failed to purge deleted accounts");
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(3600)).await;
+    }
+}
+
+// 返回验证码匹配的时间步, 允许前后各一个时间步的时钟误差
+fn matching_totp_step(secret: &[u8], code: &str) -> Option<u64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != TOTP_DIGITS || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let totp = TOTP::new_unchecked(Algorithm::SHA1, TOTP_DIGITS, 0, TOTP_STEP, secret.to_vec(), None, String::new());
+    let current = chrono::Utc::now().timestamp() as u64 / TOTP_STEP;
+    [current - 1, current, current + 1].into_iter()
+        .find(|step| totp.check(&code, step * TOTP_STEP))
+}
+
+// 恢复码格式为 xxxxx-xxxxx, 去掉了容易混淆的字符
+fn generate_recovery_code() -> String {
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut bytes = [0u8; 10];
+    OsRng.fill_bytes(&mut bytes);
+    let chars: String = bytes.iter().map(|b| ALPHABET[*b as usize % ALPHABET.len()] as char).collect();
+    format!("{}-{}", &chars[..5], &chars[5..])
+}
+
+// 用户名长度受 users.user_name 列限制
+fn validate_user_name(name: &str) -> Result<String, AuthError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > USER_NAME_MAX_CHARS {
+        return Err(AuthError::InvalidUserName(format!("User name must be 1 to {} characters", USER_NAME_MAX_CHARS)));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(AuthError::InvalidUserName("User name must not contain control characters".to_string()));
+    }
+    Ok(name.to_string())
+}
+
+// 去掉首尾空白并转为小写, 同时做基本的格式校验
+fn normalize_email(email: &str) -> Result<String, AuthError> {
+    let email = email.trim().to_lowercase();
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => !local.is_empty()
+            && !domain.contains('@')
+            && domain.split('.').count() >= 2
+            && domain.split('.').all(|label| !label.is_empty()),
+        None => false,
+    };
+
+    if !valid || email.len() > 255 || email.chars().any(char::is_whitespace) {
+        return Err(AuthError::InvalidEmail);
+    }
+    Ok(email)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_email_trims_and_lowercases() {
+        assert_eq!(normalize_email("  Alice@Example.COM ").unwrap(), "alice@example.com");
+    }
+
+    #[test]
+    fn normalize_email_rejects_malformed_addresses() {
+        let too_long = format!("{}@example.com", "a".repeat(250));
+        for email in ["", "alice", "@example.com", "alice@", "alice@example", "alice@@example.com", "alice@example..com", "al ice@example.com", &too_long] {
+            assert!(matches!(normalize_email(email), Err(AuthError::InvalidEmail)), "{email:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn validate_user_name_trims_and_checks_length() {
+        assert_eq!(validate_user_name("  Alice  ").unwrap(), "Alice");
+        // 按字符计数, 多字节字符不会提前超限
+        let max = "名".repeat(USER_NAME_MAX_CHARS);
+        assert_eq!(validate_user_name(&max).unwrap(), max);
+        for name in ["", "   ", &"a".repeat(USER_NAME_MAX_CHARS + 1)] {
+            assert!(matches!(validate_user_name(name), Err(AuthError::InvalidUserName(_))), "{name:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn validate_user_name_rejects_control_characters() {
+        assert!(matches!(validate_user_name("Al\nice"), Err(AuthError::InvalidUserName(_))));
+        assert!(matches!(validate_user_name("Al\u{7}ice"), Err(AuthError::InvalidUserName(_))));
+    }
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle {
+            account_threshold: 5,
+            ip_threshold: 20,
+            base_lockout: 60,
+            max_lockout: 3600,
+            window: 900,
+            trust_proxy_headers: false,
+        }
+    }
+
+    #[test]
+    fn lockout_doubles_after_threshold_and_is_capped() {
+        let throttle = throttle();
+        assert_eq!(throttle.lockout(5, 5), 60);
+        assert_eq!(throttle.lockout(6, 5), 120);
+        assert_eq!(throttle.lockout(8, 5), 480);
+        assert_eq!(throttle.lockout(11, 5), 3600);
+        // 指数有上限, 失败次数很大时也不会溢出
+        assert_eq!(throttle.lockout(i32::MAX, 5), 3600);
+    }
+}
@@ -1,18 +1,62 @@
-use super::Database;
-use crate::auth::{error::AuthError, model::User};
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use super::{sync_db, Database};
+use crate::auth::{error::AuthError, model::{Session, SessionDevice, TokenPurpose, UserRow, UserMfaRow}};
 
 pub(crate) trait AuthDatabase {
-    async fn get_user_by_email(&self, email: &str) -> Result<User, AuthError>;
-    async fn get_user_by_id(&self, id: &str) -> Result<User, AuthError>;
-    async fn insert_user(&self, id: &str, name: &str, email: &str, password_hash: &str) -> Result<User, AuthError>;
-    async fn is_token_blacklisted(&self, token: &str) -> Result<bool, AuthError>;
-    async fn logout_user(&self, token: &str, expires_at: chrono::DateTime<chrono::Utc>) -> Result<(), AuthError>;
+    async fn get_user_by_email(&self, email: &str) -> Result<UserRow, AuthError>;
+    async fn get_user_by_id(&self, id: &str) -> Result<UserRow, AuthError>;
+    async fn insert_user(&self, id: &str, name: &str, email: &str, password_hash: &str) -> Result<UserRow, AuthError>;
+    // 创建会话及其第一个刷新令牌
+    async fn create_session(&self, user_id: &str, session_id: &str, device: &SessionDevice, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AuthError>;
+    // 用新令牌替换旧令牌, 返回令牌所属用户和会话
+    async fn rotate_refresh_token(&self, token_hash: &str, new_token_hash: &str, expires_at: DateTime<Utc>) -> Result<(String, String), AuthError>;
+    // 会话有效时更新最后活跃时间
+    async fn touch_session(&self, user_id: &str, session_id: &str) -> Result<bool, AuthError>;
+    async fn list_sessions(&self, user_id: &str, current_session_id: &str) -> Result<Vec<Session>, AuthError>;
+    async fn revoke_session(&self, user_id: &str, session_id: &str) -> Result<bool, AuthError>;
+    // 返回被吊销的会话
+    async fn revoke_other_sessions(&self, user_id: &str, current_session_id: &str) -> Result<Vec<String>, AuthError>;
+    // 更新密码并吊销除 keep_session_id 外的所有会话, 返回被吊销的会话
+    async fn update_password(&self, user_id: &str, password_hash: &str, keep_session_id: Option<&str>) -> Result<Vec<String>, AuthError>;
+    // 创建一次性令牌, 同一用途下未使用的旧令牌失效
+    async fn create_user_token(&self, user_id: &str, purpose: TokenPurpose, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AuthError>;
+    // 使用一次性令牌, 返回令牌所属用户
+    async fn consume_user_token(&self, purpose: TokenPurpose, token_hash: &str) -> Result<String, AuthError>;
+    async fn set_email_verified(&self, user_id: &str) -> Result<(), AuthError>;
+    async fn update_user_name(&self, user_id: &str, user_name: &str) -> Result<UserRow, AuthError>;
+    async fn create_email_change_token(&self, user_id: &str, new_email: &str, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AuthError>;
+    // 使用修改邮箱令牌并更新邮箱, 返回修改前的用户和新邮箱
+    async fn change_email(&self, token_hash: &str) -> Result<(UserRow, String), AuthError>;
+    async fn get_user_mfa(&self, user_id: &str) -> Result<Option<UserMfaRow>, AuthError>;
+    // 保存待确认的 TOTP 密钥, 已开启时返回 false
+    async fn save_pending_totp(&self, user_id: &str, secret: &[u8]) -> Result<bool, AuthError>;
+    // 确认开启两步验证并替换恢复码
+    async fn enable_mfa(&self, user_id: &str, step: i64, code_hashes: &[String]) -> Result<(), AuthError>;
+    // 记录已使用的时间步, 同一验证码不能重复使用
+    async fn use_totp_step(&self, user_id: &str, step: i64) -> Result<bool, AuthError>;
+    // 返回未使用的恢复码 (id, 哈希)
+    async fn get_recovery_codes(&self, user_id: &str) -> Result<Vec<(String, String)>, AuthError>;
+    async fn use_recovery_code(&self, code_id: &str) -> Result<bool, AuthError>;
+    async fn delete_mfa(&self, user_id: &str) -> Result<(), AuthError>;
+    // 返回这些 key 中最晚的锁定截止时间
+    async fn get_login_lock(&self, keys: &[String]) -> Result<Option<DateTime<Utc>>, AuthError>;
+    // 记录一次失败并返回窗口内的失败次数, 上次失败 (或锁定结束) 早于 window_start 时重新计数
+    async fn record_login_failure(&self, key: &str, window_start: DateTime<Utc>) -> Result<i32, AuthError>;
+    async fn lock_login(&self, key: &str, locked_until: DateTime<Utc>) -> Result<(), AuthError>;
+    async fn clear_login_failures(&self, key: &str) -> Result<(), AuthError>;
+    // 标记账号待删除并吊销所有会话, 返回被吊销的会话ID
+    async fn schedule_user_deletion(&self, user_id: &str, scheduled_at: DateTime<Utc>) -> Result<Vec<String>, AuthError>;
+    async fn cancel_user_deletion(&self, user_id: &str) -> Result<(), AuthError>;
+    // 删除用户, 笔记、会话等数据通过外键级联删除
+    async fn delete_user(&self, user_id: &str) -> Result<(), AuthError>;
+    async fn delete_scheduled_users(&self, before: DateTime<Utc>) -> Result<u64, AuthError>;
 }
 
 impl AuthDatabase for Database {
-    async fn get_user_by_email(&self, email: &str) -> Result<User, AuthError> {
-        sqlx::query_as::<_, User>(
-            "SELECT * FROM users WHERE email = $1"
+    async fn get_user_by_email(&self, email: &str) -> Result<UserRow, AuthError> {
+        sqlx::query_as::<_, UserRow>(
+            "SELECT * FROM users WHERE lower(email) = lower($1)"
         )
         .bind(email)
         .fetch_one(&self.db)
@@ -23,8 +67,8 @@ impl AuthDatabase for Database {
         })
     }
 
-    async fn get_user_by_id(&self, id: &str) -> Result<User, AuthError> {
-        sqlx::query_as::<_, User>(
+    async fn get_user_by_id(&self, id: &str) -> Result<UserRow, AuthError> {
+        sqlx::query_as::<_, UserRow>(
             "SELECT * FROM users WHERE id = $1"
         )
         .bind(id)
@@ -36,9 +80,9 @@ impl AuthDatabase for Database {
         })
     }
 
-    async fn insert_user(&self, id: &str, name: &str, email: &str, password_hash: &str) -> Result<User, AuthError> {
+    async fn insert_user(&self, id: &str, name: &str, email: &str, password_hash: &str) -> Result<UserRow, AuthError> {
         let now = chrono::Utc::now();
-        sqlx::query_as::<_, User>(
+        sqlx::query_as::<_, UserRow>(
             r#"
             INSERT INTO users (id, user_name, email, password_hash, created_at, updated_at)
             VALUES ($1, $2, $3, $4, $5, $6)
@@ -53,28 +97,601 @@ impl AuthDatabase for Database {
         .bind(now)
         .fetch_one(&self.db)
         .await
-        .map_err(|e| AuthError::DatabaseError(e))
+        .map_err(|e| match e {
+            // 并发注册同一邮箱
+            sqlx::Error::Database(ref db_error) if db_error.is_unique_violation() => AuthError::UserExists,
+            _ => AuthError::DatabaseError(e),
+        })
+    }
+
+    async fn create_session(&self, user_id: &str, session_id: &str, device: &SessionDevice, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AuthError> {
+        let now = Utc::now();
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, user_id, device_id, device_name, user_agent, ip, created_at, last_seen_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $7, $8)
+            "#,
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .bind(&device.device_id)
+        .bind(&device.device_name)
+        .bind(&device.user_agent)
+        .bind(&device.ip)
+        .bind(now)
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO refresh_tokens (id, user_id, family_id, token_hash, expires_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(user_id)
+        .bind(session_id)
+        .bind(token_hash)
+        .bind(expires_at)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
     }
 
-    async fn logout_user(&self, token: &str, expires_at: chrono::DateTime<chrono::Utc>) -> Result<(), AuthError> {
+    async fn rotate_refresh_token(&self, token_hash: &str, new_token_hash: &str, expires_at: DateTime<Utc>) -> Result<(String, String), AuthError> {
+        let mut tx = self.db.begin().await?;
+
+        // 锁定旧令牌, 防止并发刷新签发多个新令牌
+        let token = sqlx::query_as::<_, (String, String, DateTime<Utc>, Option<DateTime<Utc>>, Option<DateTime<Utc>>)>(
+            r#"
+            SELECT user_id, family_id, expires_at, used_at, revoked_at FROM refresh_tokens
+            WHERE token_hash = $1
+            FOR UPDATE
+            "#,
+        )
+        .bind(token_hash)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some((user_id, family_id, token_expires_at, used_at, revoked_at)) = token else {
+            return Err(AuthError::InvalidRefreshToken);
+        };
+
+        if used_at.is_some() {
+            // 已轮换的令牌被再次使用, 可能已泄露, 吊销整个 family 及其会话
+            revoke_sessions(&mut tx, &user_id, Some(&family_id), None).await?;
+            tx.commit().await?;
+            return Err(AuthError::RefreshTokenReused(family_id));
+        }
+
+        if revoked_at.is_some() || token_expires_at < Utc::now() {
+            return Err(AuthError::InvalidRefreshToken);
+        }
+
+        let now = Utc::now();
+        sqlx::query(
+            "UPDATE refresh_tokens SET used_at = $2 WHERE token_hash = $1"
+        )
+        .bind(token_hash)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO refresh_tokens (id, user_id, family_id, token_hash, expires_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(&user_id)
+        .bind(&family_id)
+        .bind(new_token_hash)
+        .bind(expires_at)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+        // 会话随刷新令牌续期
         sqlx::query(
-            "INSERT INTO blacklisted_tokens (token, expires_at) VALUES ($1, $2)"
+            "UPDATE sessions SET last_seen_at = $2, expires_at = $3 WHERE id = $1"
         )
-        .bind(token)
+        .bind(&family_id)
+        .bind(now)
         .bind(expires_at)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok((user_id, family_id))
+    }
+
+    async fn touch_session(&self, user_id: &str, session_id: &str) -> Result<bool, AuthError> {
+        // 最后活跃时间每分钟最多更新一次, 避免每个请求都写库
+        let active = sqlx::query_scalar(
+            r#"
+            WITH active AS (
+                SELECT id, last_seen_at FROM sessions
+                WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL AND expires_at > NOW()
+            ), touched AS (
+                UPDATE sessions SET last_seen_at = NOW()
+                WHERE id IN (SELECT id FROM active WHERE last_seen_at < NOW() - INTERVAL '1 minute')
+            )
+            SELECT EXISTS (SELECT 1 FROM active)
+            "#,
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(active)
+    }
+
+    async fn list_sessions(&self, user_id: &str, current_session_id: &str) -> Result<Vec<Session>, AuthError> {
+        Ok(sqlx::query_as::<_, Session>(
+            r#"
+            SELECT id, device_id, device_name, user_agent, ip, created_at, last_seen_at, id = $2 AS current
+            FROM sessions
+            WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+            ORDER BY last_seen_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(current_session_id)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn revoke_session(&self, user_id: &str, session_id: &str) -> Result<bool, AuthError> {
+        let mut tx = self.db.begin().await?;
+        let revoked = revoke_sessions(&mut tx, user_id, Some(session_id), None).await?;
+        tx.commit().await?;
+        Ok(!revoked.is_empty())
+    }
+
+    async fn revoke_other_sessions(&self, user_id: &str, current_session_id: &str) -> Result<Vec<String>, AuthError> {
+        let mut tx = self.db.begin().await?;
+        let revoked = revoke_sessions(&mut tx, user_id, None, Some(current_session_id)).await?;
+        tx.commit().await?;
+        Ok(revoked)
+    }
+
+    async fn update_password(&self, user_id: &str, password_hash: &str, keep_session_id: Option<&str>) -> Result<Vec<String>, AuthError> {
+        let mut tx = self.db.begin().await?;
+
+        let result = sqlx::query(
+            "UPDATE users SET password_hash = $2, updated_at = $3 WHERE id = $1"
+        )
+        .bind(user_id)
+        .bind(password_hash)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        let revoked = revoke_sessions(&mut tx, user_id, None, keep_session_id).await?;
+        tx.commit().await?;
+        Ok(revoked)
+    }
+
+    async fn create_user_token(&self, user_id: &str, purpose: TokenPurpose, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AuthError> {
+        let mut tx = self.db.begin().await?;
+        insert_user_token(&mut tx, user_id, purpose, token_hash, None, expires_at).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn consume_user_token(&self, purpose: TokenPurpose, token_hash: &str) -> Result<String, AuthError> {
+        // 条件更新保证令牌只能使用一次
+        sqlx::query_scalar::<_, String>(
+            r#"
+            UPDATE user_tokens SET used_at = NOW()
+            WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING user_id
+            "#,
+        )
+        .bind(token_hash)
+        .bind(purpose)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AuthError::InvalidToken)
+    }
+
+    async fn set_email_verified(&self, user_id: &str) -> Result<(), AuthError> {
+        sqlx::query(
+            "UPDATE users SET email_verified = TRUE, updated_at = $2 WHERE id = $1"
+        )
+        .bind(user_id)
+        .bind(Utc::now())
         .execute(&self.db)
         .await?;
         Ok(())
     }
 
-    async fn is_token_blacklisted(&self, token: &str) -> Result<bool, AuthError> {
-        let exists = sqlx::query_scalar(
-            "SELECT EXISTS (SELECT 1 FROM blacklisted_tokens WHERE token = $1)"
+    async fn update_user_name(&self, user_id: &str, user_name: &str) -> Result<UserRow, AuthError> {
+        sqlx::query_as::<_, UserRow>(
+            "UPDATE users SET user_name = $2, updated_at = $3 WHERE id = $1 RETURNING *"
+        )
+        .bind(user_id)
+        .bind(user_name)
+        .bind(Utc::now())
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AuthError::UserNotFound)
+    }
+
+    async fn create_email_change_token(&self, user_id: &str, new_email: &str, token_hash: &str, expires_at: DateTime<Utc>) -> Result<(), AuthError> {
+        let mut tx = self.db.begin().await?;
+        insert_user_token(&mut tx, user_id, TokenPurpose::EmailChange, token_hash, Some(new_email), expires_at).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn change_email(&self, token_hash: &str) -> Result<(UserRow, String), AuthError> {
+        let mut tx = self.db.begin().await?;
+
+        let (user_id, new_email) = sqlx::query_as::<_, (String, String)>(
+            r#"
+            UPDATE user_tokens SET used_at = NOW()
+            WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > NOW()
+              AND new_email IS NOT NULL
+            RETURNING user_id, new_email
+            "#,
         )
-        .bind(token)
+        .bind(token_hash)
+        .bind(TokenPurpose::EmailChange)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AuthError::InvalidToken)?;
+
+        let user = sqlx::query_as::<_, UserRow>(
+            "SELECT * FROM users WHERE id = $1 FOR UPDATE"
+        )
+        .bind(&user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AuthError::UserNotFound)?;
+
+        // 申请后新邮箱可能已被其他账号注册
+        let taken = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM users WHERE lower(email) = lower($1) AND id <> $2)"
+        )
+        .bind(&new_email)
+        .bind(&user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if taken {
+            return Err(AuthError::EmailInUse);
+        }
+
+        // 新邮箱已通过令牌验证
+        sqlx::query(
+            "UPDATE users SET email = $2, email_verified = TRUE, updated_at = $3 WHERE id = $1"
+        )
+        .bind(&user_id)
+        .bind(&new_email)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref db_error) if db_error.is_unique_violation() => AuthError::EmailInUse,
+            _ => AuthError::DatabaseError(e),
+        })?;
+
+        // 发往旧邮箱的令牌全部作废
+        sqlx::query(
+            "DELETE FROM user_tokens WHERE user_id = $1 AND used_at IS NULL"
+        )
+        .bind(&user_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok((user, new_email))
+    }
+
+    async fn get_user_mfa(&self, user_id: &str) -> Result<Option<UserMfaRow>, AuthError> {
+        Ok(sqlx::query_as::<_, UserMfaRow>(
+            "SELECT * FROM user_mfa WHERE user_id = $1"
+        )
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?)
+    }
+
+    async fn save_pending_totp(&self, user_id: &str, secret: &[u8]) -> Result<bool, AuthError> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO user_mfa (user_id, totp_secret, created_at) VALUES ($1, $2, $3)
+            ON CONFLICT (user_id) DO UPDATE
+            SET totp_secret = EXCLUDED.totp_secret, created_at = EXCLUDED.created_at, last_used_step = NULL
+            WHERE user_mfa.enabled_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(secret)
+        .bind(Utc::now())
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn enable_mfa(&self, user_id: &str, step: i64, code_hashes: &[String]) -> Result<(), AuthError> {
+        let now = Utc::now();
+        let mut tx = self.db.begin().await?;
+
+        let result = sqlx::query(
+            "UPDATE user_mfa SET enabled_at = $2, last_used_step = $3 WHERE user_id = $1 AND enabled_at IS NULL"
+        )
+        .bind(user_id)
+        .bind(now)
+        .bind(step)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(AuthError::MfaAlreadyEnabled);
+        }
+
+        sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for code_hash in code_hashes {
+            sqlx::query(
+                "INSERT INTO mfa_recovery_codes (id, user_id, code_hash, created_at) VALUES ($1, $2, $3, $4)"
+            )
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(user_id)
+            .bind(code_hash)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn use_totp_step(&self, user_id: &str, step: i64) -> Result<bool, AuthError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE user_mfa SET last_used_step = $2
+            WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+            "#,
+        )
+        .bind(user_id)
+        .bind(step)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_recovery_codes(&self, user_id: &str) -> Result<Vec<(String, String)>, AuthError> {
+        Ok(sqlx::query_as::<_, (String, String)>(
+            "SELECT id, code_hash FROM mfa_recovery_codes WHERE user_id = $1 AND used_at IS NULL"
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn use_recovery_code(&self, code_id: &str) -> Result<bool, AuthError> {
+        let result = sqlx::query(
+            "UPDATE mfa_recovery_codes SET used_at = NOW() WHERE id = $1 AND used_at IS NULL"
+        )
+        .bind(code_id)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_mfa(&self, user_id: &str) -> Result<(), AuthError> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let result = sqlx::query("DELETE FROM user_mfa WHERE user_id = $1 AND enabled_at IS NOT NULL")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(AuthError::MfaNotEnabled);
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_login_lock(&self, keys: &[String]) -> Result<Option<DateTime<Utc>>, AuthError> {
+        Ok(sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+            "SELECT MAX(locked_until) FROM login_attempts WHERE key = ANY($1) AND locked_until > NOW()"
+        )
+        .bind(keys)
         .fetch_one(&self.db)
+        .await?)
+    }
+
+    async fn record_login_failure(&self, key: &str, window_start: DateTime<Utc>) -> Result<i32, AuthError> {
+        Ok(sqlx::query_scalar::<_, i32>(
+            r#"
+            INSERT INTO login_attempts (key, failures, last_failure_at) VALUES ($1, 1, $2)
+            ON CONFLICT (key) DO UPDATE SET
+                failures = CASE
+                    WHEN GREATEST(login_attempts.last_failure_at, login_attempts.locked_until) < $3 THEN 1
+                    ELSE login_attempts.failures + 1
+                END,
+                last_failure_at = EXCLUDED.last_failure_at
+            RETURNING failures
+            "#,
+        )
+        .bind(key)
+        .bind(Utc::now())
+        .bind(window_start)
+        .fetch_one(&self.db)
+        .await?)
+    }
+
+    async fn lock_login(&self, key: &str, locked_until: DateTime<Utc>) -> Result<(), AuthError> {
+        sqlx::query(
+            "UPDATE login_attempts SET locked_until = $2 WHERE key = $1"
+        )
+        .bind(key)
+        .bind(locked_until)
+        .execute(&self.db)
         .await?;
+        Ok(())
+    }
 
-        Ok(exists)
+    async fn clear_login_failures(&self, key: &str) -> Result<(), AuthError> {
+        sqlx::query("DELETE FROM login_attempts WHERE key = $1")
+            .bind(key)
+            .execute(&self.db)
+            .await?;
+        Ok(())
     }
-}
\ No newline at end of file
+
+    async fn schedule_user_deletion(&self, user_id: &str, scheduled_at: DateTime<Utc>) -> Result<Vec<String>, AuthError> {
+        let mut tx = self.db.begin().await?;
+
+        let result = sqlx::query(
+            "UPDATE users SET deletion_scheduled_at = $2, updated_at = $3 WHERE id = $1"
+        )
+        .bind(user_id)
+        .bind(scheduled_at)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        let revoked = revoke_sessions(&mut tx, user_id, None, None).await?;
+        tx.commit().await?;
+        Ok(revoked)
+    }
+
+    async fn cancel_user_deletion(&self, user_id: &str) -> Result<(), AuthError> {
+        sqlx::query(
+            "UPDATE users SET deletion_scheduled_at = NULL, updated_at = $2 WHERE id = $1"
+        )
+        .bind(user_id)
+        .bind(Utc::now())
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_user(&self, user_id: &str) -> Result<(), AuthError> {
+        let mut tx = self.db.begin().await?;
+        sync_db::revoke_owner_shares(&mut tx, user_id).await?;
+
+        let result = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_scheduled_users(&self, before: DateTime<Utc>) -> Result<u64, AuthError> {
+        let mut tx = self.db.begin().await?;
+
+        // 锁定待删除的账号, 防止删除期间登录取消删除
+        let user_ids = sqlx::query_scalar::<_, String>(
+            "SELECT id FROM users WHERE deletion_scheduled_at IS NOT NULL AND deletion_scheduled_at <= $1 FOR UPDATE"
+        )
+        .bind(before)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for user_id in user_ids.iter() {
+            sync_db::revoke_owner_shares(&mut tx, user_id).await?;
+        }
+
+        let result = sqlx::query("DELETE FROM users WHERE id = ANY($1)")
+            .bind(&user_ids)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+}
+
+// 同一用途只保留最新的未使用令牌
+async fn insert_user_token(conn: &mut PgConnection, user_id: &str, purpose: TokenPurpose, token_hash: &str, new_email: Option<&str>, expires_at: DateTime<Utc>) -> Result<(), AuthError> {
+    sqlx::query(
+        "DELETE FROM user_tokens WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL"
+    )
+    .bind(user_id)
+    .bind(purpose)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_tokens (token_hash, user_id, purpose, new_email, expires_at, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(token_hash)
+    .bind(user_id)
+    .bind(purpose)
+    .bind(new_email)
+    .bind(expires_at)
+    .bind(Utc::now())
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+// 吊销会话及其刷新令牌, session_id 为空时吊销除 except 外的所有会话
+async fn revoke_sessions(conn: &mut PgConnection, user_id: &str, session_id: Option<&str>, except: Option<&str>) -> Result<Vec<String>, AuthError> {
+    let now = Utc::now();
+    let session_ids = sqlx::query_scalar::<_, String>(
+        r#"
+        UPDATE sessions SET revoked_at = $4
+        WHERE user_id = $1 AND revoked_at IS NULL
+          AND ($2::text IS NULL OR id = $2)
+          AND ($3::text IS NULL OR id <> $3)
+        RETURNING id
+        "#,
+    )
+    .bind(user_id)
+    .bind(session_id)
+    .bind(except)
+    .bind(now)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = $2 WHERE family_id = ANY($1) AND revoked_at IS NULL"
+    )
+    .bind(&session_ids)
+    .bind(now)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(session_ids)
+}
@@ -39,11 +39,25 @@ impl Database {
 
     // 清理过期token
     pub async fn cleanup_expired_tokens(&self) {
-        sqlx::query(
-            "DELETE FROM blacklisted_tokens WHERE expires_at < NOW()"
-        )
-        .execute(&self.db)
-        .await.expect("Failed to cleanup expired tokens");
+        // 后台任务中某项清理失败只记录日志, 不影响其余清理和后续轮次
+        let cleanups = [
+            ("refresh tokens", "DELETE FROM refresh_tokens WHERE expires_at < NOW()"),
+            ("sessions", "DELETE FROM sessions WHERE expires_at < NOW() OR revoked_at < NOW() - INTERVAL '30 days'"),
+            ("user tokens", "DELETE FROM user_tokens WHERE expires_at < NOW()"),
+            (
+                "login attempts",
+                r#"
+                DELETE FROM login_attempts
+                WHERE last_failure_at < NOW() - INTERVAL '1 day' AND (locked_until IS NULL OR locked_until < NOW())
+                "#,
+            ),
+        ];
+
+        for (name, sql) in cleanups {
+            if let Err(e) = sqlx::query(sql).execute(&self.db).await {
+                tracing::error!(error = %e, "Failed to cleanup expired {}", name);
+            }
+        }
     }
     
 }
\ No newline at end of file
@@ -1,262 +1,1675 @@
+use std::collections::{HashMap, HashSet};
 use chrono::{DateTime, Utc};
-use crate::sync::{error::SyncError, model::{Note, NoteCreate, NoteImport, NoteRow, NoteUpdate}};
+use sqlx::{PgConnection, PgExecutor};
+use crate::sync::{error::SyncError, model::{ChangeEntity, ChangeKind, ExportedTrashNote, Note, NoteChange, NoteChangeRow, NoteCreate, NoteImport, NoteListCursor, NoteListQuery, NoteRevision, NoteRevisionSummary, NoteRow, NoteSearchHit, NoteSearchQuery, NoteSearchRow, NoteSort, NoteSummary, NoteSummaryRow, NoteUpdate, Notebook, NotebookCreate, NotesExport, Share, SharePermission, SortOrder, SyncOperation, SyncPage, SyncOperationResult, TagSummary, TrashedNote}};
 use super::Database;
 
+// 共享给用户 ($1) 的笔记本及其子笔记本
+const SHARED_NOTEBOOKS: &str = r#"
+    WITH RECURSIVE shared_notebooks AS (
+        SELECT notebook_id AS id FROM shares WHERE recipient_id = $1 AND notebook_id IS NOT NULL
+        UNION
+        SELECT nb.id FROM notebooks nb JOIN shared_notebooks s ON nb.parent_id = s.id
+    )
+"#;
+
+// 用户 ($1) 可以访问笔记 n: 自己的笔记, 直接共享的笔记, 或共享笔记本中的笔记
+const ACCESSIBLE_NOTE: &str = r#"(
+    n.user_id = $1
+    OR n.id IN (SELECT note_id FROM shares WHERE recipient_id = $1 AND note_id IS NOT NULL)
+    OR n.notebook_id IN (SELECT id FROM shared_notebooks)
+)"#;
+
+// 笔记 ($1) 所在的笔记本及其所有上级笔记本
+const NOTE_ANCESTORS: &str = r#"
+    WITH RECURSIVE ancestors AS (
+        SELECT nb.id, nb.parent_id FROM notebooks nb JOIN notes n ON n.notebook_id = nb.id WHERE n.id = $1
+        UNION ALL
+        SELECT nb.id, nb.parent_id FROM notebooks nb JOIN ancestors a ON nb.id = a.parent_id
+    )
+"#;
+
+const SHARE_SELECT: &str = r#"
+    SELECT
+        s.id, s.note_id, s.notebook_id,
+        s.owner_id, o.email AS owner_email,
+        s.recipient_id, r.email AS recipient_email,
+        s.permission, s.created_at
+    FROM shares s
+    JOIN users o ON o.id = s.owner_id
+    JOIN users r ON r.id = s.recipient_id
+"#;
+
 pub(crate) trait SyncDatabase {
-    async fn create_note(&self, user_id: &str, note_id: &str, note: &NoteCreate) -> Result<(), SyncError>;
-    async fn import_note(&self, user_id: &str, note_id: &str, note: &NoteImport) -> Result<(), SyncError>;
+    // 写操作返回本次提交的变更, 用于实时通知
+    async fn create_note(&self, user_id: &str, note_id: &str, note: &NoteCreate) -> Result<Vec<NoteChange>, SyncError>;
+    async fn import_note(&self, user_id: &str, note_id: &str, note: &NoteImport) -> Result<Vec<NoteChange>, SyncError>;
     async fn get_note(&self, user_id: &str, note_id: &str) -> Result<Note, SyncError>;
-    async fn update_note(&self, user_id: &str, note_id: &str, update: NoteUpdate) -> Result<Note, SyncError>;
-    async fn delete_note(&self, user_id: &str, note_id: &str) -> Result<(), SyncError>;
-    async fn get_sync_notes(&self, user_id: &str, time: DateTime<Utc>) -> Result<(Vec<Note>, Vec<String>), SyncError>;
+    async fn update_note(&self, user_id: &str, note_id: &str, update: NoteUpdate) -> Result<(Note, Vec<NoteChange>), SyncError>;
+    async fn delete_note(&self, user_id: &str, note_id: &str) -> Result<Vec<NoteChange>, SyncError>;
+    async fn apply_sync_operations(&self, user_id: &str, operations: Vec<SyncOperation>) -> Result<(Vec<SyncOperationResult>, Vec<NoteChange>), SyncError>;
+    async fn get_sync_notes(&self, user_id: &str, after_seq: i64, limit: i64) -> Result<SyncPage, SyncError>;
+    async fn get_changes_since(&self, user_id: &str, after_seq: i64, limit: i64) -> Result<Vec<NoteChange>, SyncError>;
+    async fn get_compacted_seq(&self, user_id: &str) -> Result<i64, SyncError>;
+    // 压缩早于指定时间的删除记录, 返回删除的变更记录数
+    async fn compact_tombstones(&self, before: DateTime<Utc>) -> Result<u64, SyncError>;
+    async fn list_notes(&self, user_id: &str, query: &NoteListQuery, cursor: Option<&NoteListCursor>, limit: i64) -> Result<Vec<NoteSummary>, SyncError>;
+    async fn search_notes(&self, user_id: &str, query: &NoteSearchQuery, limit: i64, offset: i64) -> Result<Vec<NoteSearchHit>, SyncError>;
+    async fn list_note_revisions(&self, user_id: &str, note_id: &str) -> Result<Vec<NoteRevisionSummary>, SyncError>;
+    async fn get_note_revision(&self, user_id: &str, note_id: &str, revision: i64) -> Result<NoteRevision, SyncError>;
+    async fn restore_note_revision(&self, user_id: &str, note_id: &str, revision: i64) -> Result<(Note, Vec<NoteChange>), SyncError>;
+    async fn list_trash(&self, user_id: &str) -> Result<Vec<TrashedNote>, SyncError>;
+    async fn restore_note(&self, user_id: &str, note_id: &str) -> Result<(Note, Vec<NoteChange>), SyncError>;
+    // 永久删除回收站中的笔记, note_id 为空时清空整个回收站
+    async fn empty_trash(&self, user_id: &str, note_id: Option<&str>) -> Result<u64, SyncError>;
+    async fn purge_trash(&self, before: DateTime<Utc>) -> Result<u64, SyncError>;
+    async fn list_tags(&self, user_id: &str) -> Result<Vec<TagSummary>, SyncError>;
+    // 将用户所有笔记上的标签替换为新标签, 新标签为空时删除
+    async fn replace_tag(&self, user_id: &str, tag: &str, new_tag: Option<&str>) -> Result<Vec<NoteChange>, SyncError>;
+    // 导出用户的所有笔记、回收站和历史版本
+    async fn export_notes(&self, user_id: &str) -> Result<NotesExport, SyncError>;
+    async fn list_notebooks(&self, user_id: &str) -> Result<Vec<Notebook>, SyncError>;
+    async fn get_notebook(&self, user_id: &str, notebook_id: &str) -> Result<Notebook, SyncError>;
+    async fn create_notebook(&self, user_id: &str, notebook_id: &str, notebook: &NotebookCreate) -> Result<(Notebook, Vec<NoteChange>), SyncError>;
+    // 重命名或移动笔记本, parent_id 为 Some(None) 时移到顶层
+    async fn update_notebook(&self, user_id: &str, notebook_id: &str, name: Option<&str>, parent_id: Option<Option<&str>>, base_revision: Option<i64>, updated_at: DateTime<Utc>) -> Result<(Notebook, Vec<NoteChange>), SyncError>;
+    // 删除笔记本及其子笔记本, 其中的笔记移入回收站
+    async fn delete_notebook(&self, user_id: &str, notebook_id: &str) -> Result<Vec<NoteChange>, SyncError>;
+    // 共享给已注册用户, 重复共享时更新权限
+    async fn create_share(&self, owner_id: &str, share_id: &str, recipient_email: &str, note_id: Option<&str>, notebook_id: Option<&str>, permission: SharePermission) -> Result<(Share, Vec<NoteChange>), SyncError>;
+    async fn list_shares(&self, owner_id: &str) -> Result<Vec<Share>, SyncError>;
+    async fn list_received_shares(&self, recipient_id: &str) -> Result<Vec<Share>, SyncError>;
+    // 所有者撤销或接收者退出共享
+    async fn delete_share(&self, user_id: &str, share_id: &str) -> Result<Vec<NoteChange>, SyncError>;
 }
 
 impl SyncDatabase for Database {
-    async fn create_note(&self, user_id: &str, note_id: &str, note: &NoteCreate) -> Result<(), SyncError> {
+    async fn create_note(&self, user_id: &str, note_id: &str, note: &NoteCreate) -> Result<Vec<NoteChange>, SyncError> {
         let mut tx = self.db.begin().await?;
-        // 插入主表
-        sqlx::query(
+        let mut changes = Vec::new();
+        insert_note(&mut tx, user_id, note_id, note, &mut changes).await?;
+        tx.commit().await?;
+        println!("create note db success");
+
+        Ok(changes)
+    }
+
+    async fn import_note(&self, user_id: &str, note_id: &str, note: &NoteImport) -> Result<Vec<NoteChange>, SyncError> {
+        let mut tx = self.db.begin().await?;
+        let mut changes = Vec::new();
+        upsert_note(&mut tx, user_id, note_id, note, &mut changes).await?;
+        tx.commit().await?;
+        println!("import note db success");
+
+        Ok(changes)
+    }
+
+    async fn get_note(&self, user_id: &str, note_id: &str) -> Result<Note, SyncError> {
+        let note_row = sqlx::query_as::<_, NoteRow>(
+            "SELECT * FROM notes WHERE id = $1 AND deleted_at IS NULL"
+        )
+        .bind(note_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(SyncError::NotFound)?;
+
+        // 共享给当前用户的笔记也可以读取, 无权访问时不暴露笔记是否存在
+        if note_row.user_id != user_id && note_permission(&self.db, user_id, note_id).await?.is_none() {
+            return Err(SyncError::NotFound);
+        }
+
+        self.add_note_with_tags(note_row).await
+    }
+
+    async fn update_note(&self, user_id: &str, note_id: &str, update: NoteUpdate) -> Result<(Note, Vec<NoteChange>), SyncError> {
+        let mut tx = self.db.begin().await?;
+        let mut changes = Vec::new();
+        // 冲突时事务随 tx 丢弃回滚
+        let note = apply_note_update(&mut tx, user_id, note_id, update, &mut changes).await?;
+        tx.commit().await?;
+        Ok((note, changes))
+    }
+
+    async fn delete_note(&self, user_id: &str, note_id: &str) -> Result<Vec<NoteChange>, SyncError> {
+        let mut tx = self.db.begin().await?;
+        let mut changes = Vec::new();
+        remove_note(&mut tx, user_id, note_id, &mut changes).await?;
+        tx.commit().await?;
+        Ok(changes)
+    }
+
+    async fn apply_sync_operations(&self, user_id: &str, operations: Vec<SyncOperation>) -> Result<(Vec<SyncOperationResult>, Vec<NoteChange>), SyncError> {
+        // 所有操作在同一事务中执行, 冲突和拒绝只影响单个操作, 数据库错误则整体回滚
+        let mut tx = self.db.begin().await?;
+        let mut results = Vec::with_capacity(operations.len());
+        let mut changes = Vec::new();
+
+        for operation in operations {
+            let result = match operation {
+                SyncOperation::Create { note_id, note } => {
+                    if note_exists(&mut tx, &note_id).await? {
+                        SyncOperationResult::rejected(note_id, "Note already exists")
+                    } else if !notebook_exists(&mut tx, user_id, note.notebook_id.as_deref()).await? {
+                        SyncOperationResult::rejected(note_id, "Notebook not found")
+                    } else {
+                        insert_note(&mut tx, user_id, &note_id, &note, &mut changes).await?;
+                        let note = fetch_note(&mut tx, user_id, &note_id).await?;
+                        SyncOperationResult::accepted(note_id, Some(note))
+                    }
+                }
+                SyncOperation::Update { note_id, update } => {
+                    apply_update_operation(&mut tx, user_id, note_id, update, &mut changes).await?
+                }
+                SyncOperation::Tags { note_id, tags, base_revision, updated_at } => {
+                    let update = NoteUpdate {
+                        title: None,
+                        content: None,
+                        tags: Some(tags),
+                        notebook_id: None,
+                        base_revision,
+                        updated_at,
+                    };
+                    apply_update_operation(&mut tx, user_id, note_id, update, &mut changes).await?
+                }
+                SyncOperation::Move { note_id, notebook_id, base_revision, updated_at } => {
+                    let update = NoteUpdate {
+                        title: None,
+                        content: None,
+                        tags: None,
+                        notebook_id: Some(notebook_id),
+                        base_revision,
+                        updated_at,
+                    };
+                    apply_update_operation(&mut tx, user_id, note_id, update, &mut changes).await?
+                }
+                SyncOperation::Delete { note_id } => {
+                    match remove_note(&mut tx, user_id, &note_id, &mut changes).await {
+                        Ok(()) => SyncOperationResult::accepted(note_id, None),
+                        Err(SyncError::Forbidden) => SyncOperationResult::rejected(note_id, "Permission denied"),
+                        Err(e) => return Err(e),
+                    }
+                }
+            };
+            results.push(result);
+        }
+
+        tx.commit().await?;
+        Ok((results, changes))
+    }
+
+    async fn get_sync_notes(&self, user_id: &str, after_seq: i64, limit: i64) -> Result<SyncPage, SyncError> {
+        // 按序号顺序获取游标之后每个笔记和笔记本的最新变更, 多取一条判断是否还有后续
+        let mut changes = sqlx::query_as::<_, NoteChangeRow>(
             r#"
-            INSERT INTO notes (id, user_id, title, content, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6)
+            SELECT entity, note_id, kind, seq FROM (
+                SELECT DISTINCT ON (entity, note_id) entity, note_id, kind, seq FROM note_changes
+                WHERE user_id = $1 AND seq > $2
+                ORDER BY entity, note_id, seq DESC
+            ) latest
+            ORDER BY seq ASC
+            LIMIT $3
             "#,
         )
-        .bind(note_id)
         .bind(user_id)
-        .bind(&note.title)
-        .bind("")
-        .bind(note.created_at)
-        .bind(note.created_at)
-        .execute(&mut *tx)
+        .bind(after_seq)
+        .bind(limit + 1)
+        .fetch_all(&self.db)
         .await?;
 
-        tx.commit().await?;
-        println!("create note db success");
+        let has_more = changes.len() as i64 > limit;
+        changes.truncate(limit as usize);
+        let last_seq = changes.last().map_or(after_seq, |c| c.seq);
+
+        let mut upserted_ids = Vec::new();
+        let mut deleted_note_ids = Vec::new();
+        let mut upserted_notebook_ids = Vec::new();
+        let mut deleted_notebook_ids = Vec::new();
+        for change in changes {
+            match (change.entity, change.kind) {
+                (ChangeEntity::Note, ChangeKind::Created | ChangeKind::Updated) => upserted_ids.push(change.note_id),
+                (ChangeEntity::Note, ChangeKind::Deleted) => deleted_note_ids.push(change.note_id),
+                (ChangeEntity::Notebook, ChangeKind::Created | ChangeKind::Updated) => upserted_notebook_ids.push(change.note_id),
+                (ChangeEntity::Notebook, ChangeKind::Deleted) => deleted_notebook_ids.push(change.note_id),
+            }
+        }
+
+        // 自己的和共享给自己的笔记本
+        let notebooks = sqlx::query_as::<_, Notebook>(&format!(
+            r#"
+            {SHARED_NOTEBOOKS}
+            SELECT * FROM notebooks
+            WHERE id = ANY($2) AND (user_id = $1 OR id IN (SELECT id FROM shared_notebooks))
+            "#,
+        ))
+        .bind(user_id)
+        .bind(&upserted_notebook_ids)
+        .fetch_all(&self.db)
+        .await?;
+        let mut notebooks: HashMap<String, Notebook> = notebooks.into_iter().map(|notebook| (notebook.id.clone(), notebook)).collect();
+        let mut visible_notebooks = Vec::with_capacity(upserted_notebook_ids.len());
+        for id in upserted_notebook_ids {
+            match notebooks.remove(&id) {
+                Some(notebook) => visible_notebooks.push(notebook),
+                None => deleted_notebook_ids.push(id),
+            }
+        }
 
-        Ok(())
+        //  获取变更的笔记, 包括共享给当前用户的笔记
+        let rows = sqlx::query_as::<_, NoteRow>(&format!(
+            r#"
+            {SHARED_NOTEBOOKS}
+            SELECT n.* FROM notes n
+            WHERE n.id = ANY($2) AND n.deleted_at IS NULL AND {ACCESSIBLE_NOTE}
+            "#,
+        ))
+        .bind(user_id)
+        .bind(&upserted_ids)
+        .fetch_all(&self.db)
+        .await?;
+
+        // 一次查询获取所有标签
+        let mut tags = fetch_tags_for_notes(&self.db, &upserted_ids).await?;
+        let mut rows: HashMap<String, NoteRow> = rows.into_iter().map(|row| (row.id.clone(), row)).collect();
+
+        // 保持变更序号顺序, 已删除或已取消共享的笔记作为删除下发
+        let mut notes = Vec::with_capacity(upserted_ids.len());
+        for id in upserted_ids {
+            match rows.remove(&id) {
+                Some(row) => {
+                    let note_tags = tags.remove(&row.id).unwrap_or_default();
+                    notes.push(row.into_note(note_tags));
+                }
+                None => deleted_note_ids.push(id),
+            }
+        }
+        let notebooks = visible_notebooks;
+
+        Ok(SyncPage { notebooks, deleted_notebook_ids, notes, deleted_note_ids, last_seq, has_more })
     }
 
-    async fn import_note(&self, user_id: &str, note_id: &str, note: &NoteImport) -> Result<(), SyncError> {
-        let mut tx = self.db.begin().await?;
-        // 插入主表
-        sqlx::query(
+    async fn get_changes_since(&self, user_id: &str, after_seq: i64, limit: i64) -> Result<Vec<NoteChange>, SyncError> {
+        Ok(sqlx::query_as::<_, NoteChange>(
             r#"
-            INSERT INTO notes (id, user_id, title, content, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6)
-            ON CONFLICT (id) DO UPDATE SET
-                user_id = EXCLUDED.user_id,
-                title = EXCLUDED.title,
-                content = EXCLUDED.content,
-                created_at = EXCLUDED.created_at,
-                updated_at = EXCLUDED.updated_at
+            SELECT * FROM note_changes
+            WHERE user_id = $1 AND seq > $2
+            ORDER BY seq ASC
+            LIMIT $3
             "#,
         )
-        .bind(note_id)
         .bind(user_id)
-        .bind(&note.title)
-        .bind(&note.content)
-        .bind(note.created_at)
-        .bind(note.updated_at)
+        .bind(after_seq)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn get_compacted_seq(&self, user_id: &str) -> Result<i64, SyncError> {
+        let compacted_seq = sqlx::query_scalar::<_, i64>(
+            "SELECT compacted_seq FROM user_sync_state WHERE user_id = $1"
+        )
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(compacted_seq.unwrap_or(0))
+    }
+
+    async fn compact_tombstones(&self, before: DateTime<Utc>) -> Result<u64, SyncError> {
+        let mut tx = self.db.begin().await?;
+
+        // 被同一笔记后续变更覆盖的记录不影响增量同步, 直接删除
+        let superseded = sqlx::query(
+            r#"
+            DELETE FROM note_changes c
+            WHERE c.changed_at < $1 AND EXISTS (
+                SELECT 1 FROM note_changes newer
+                WHERE newer.user_id = c.user_id AND newer.entity = c.entity
+                  AND newer.note_id = c.note_id AND newer.seq > c.seq
+            )
+            "#,
+        )
+        .bind(before)
         .execute(&mut *tx)
         .await?;
 
-        // 先删除旧标签
+        // 先推进用户的压缩序号, 再删除过期的删除记录
         sqlx::query(
-            "DELETE FROM note_tags WHERE note_id = $1"
+            r#"
+            UPDATE user_sync_state s
+            SET compacted_seq = GREATEST(s.compacted_seq, c.max_seq)
+            FROM (
+                SELECT user_id, MAX(seq) AS max_seq FROM note_changes
+                WHERE kind = 'deleted' AND changed_at < $1
+                GROUP BY user_id
+            ) c
+            WHERE s.user_id = c.user_id
+            "#,
         )
-        .bind(note_id)
+        .bind(before)
         .execute(&mut *tx)
         .await?;
 
-        // 插入新标签
-        for tag in note.tags.iter() {
-            sqlx::query(
-                "INSERT INTO note_tags (note_id, tag) VALUES ($1, $2)"
-            )
-            .bind(note_id)
-            .bind(tag)
+        let tombstones = sqlx::query(
+            "DELETE FROM note_changes WHERE kind = 'deleted' AND changed_at < $1"
+        )
+        .bind(before)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("DELETE FROM deleted_notes WHERE deleted_at < $1")
+            .bind(before)
             .execute(&mut *tx)
             .await?;
-        }
 
         tx.commit().await?;
-        println!("import note db success");
 
-        Ok(())
+        Ok(superseded.rows_affected() + tombstones.rows_affected())
     }
 
-    async fn get_note(&self, user_id: &str, note_id: &str) -> Result<Note, SyncError> {
-        let note_row = sqlx::query_as::<_, NoteRow>(
-            "SELECT * FROM notes WHERE id = $1 AND user_id = $2"
+    async fn list_notes(&self, user_id: &str, query: &NoteListQuery, cursor: Option<&NoteListCursor>, limit: i64) -> Result<Vec<NoteSummary>, SyncError> {
+        // 排序列和方向来自枚举, 可以安全拼接; 时间键 ($9) 和标题键 ($5) 分开绑定
+        let (column, key_param) = match query.sort {
+            NoteSort::UpdatedAt => ("updated_at", "$9"),
+            NoteSort::CreatedAt => ("created_at", "$9"),
+            NoteSort::Title => ("title", "$5"),
+        };
+        let (op, direction) = match query.order {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+
+        let sql = format!(
+            r#"
+            SELECT n.id, n.notebook_id, n.title, LEFT(n.content, 200) AS preview, n.revision, n.created_at, n.updated_at
+            FROM notes n
+            WHERE n.user_id = $1
+                AND n.deleted_at IS NULL
+                AND ($2::text IS NULL OR EXISTS (
+                    SELECT 1 FROM note_tags t WHERE t.note_id = n.id AND t.tag = $2
+                ))
+                AND ($3::timestamptz IS NULL OR n.updated_at >= $3)
+                AND ($4::timestamptz IS NULL OR n.updated_at <= $4)
+                AND ($6::text IS NULL OR (n.{column}, n.id) {op} ({key_param}, $6))
+                AND ($8::text IS NULL OR n.notebook_id = $8)
+            ORDER BY n.{column} {direction}, n.id {direction}
+            LIMIT $7
+            "#,
+        );
+
+        let rows = sqlx::query_as::<_, NoteSummaryRow>(&sql)
+            .bind(user_id)
+            .bind(&query.tag)
+            .bind(query.from)
+            .bind(query.to)
+            .bind(cursor.filter(|c| c.sort == NoteSort::Title).map(|c| &c.key))
+            .bind(cursor.map(|c| &c.id))
+            .bind(limit)
+            .bind(&query.notebook_id)
+            .bind(cursor.and_then(NoteListCursor::time_key))
+            .fetch_all(&self.db)
+            .await?;
+
+        // 一次查询获取所有标签
+        let ids: Vec<String> = rows.iter().map(|row| row.id.clone()).collect();
+        let mut tags = fetch_tags_for_notes(&self.db, &ids).await?;
+
+        Ok(rows.into_iter()
+            .map(|row| NoteSummary {
+                tags: tags.remove(&row.id).unwrap_or_default(),
+                id: row.id,
+                notebook_id: row.notebook_id,
+                title: row.title,
+                preview: row.preview,
+                revision: row.revision,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+            })
+            .collect())
+    }
+
+    async fn search_notes(&self, user_id: &str, query: &NoteSearchQuery, limit: i64, offset: i64) -> Result<Vec<NoteSearchHit>, SyncError> {
+        let rows = sqlx::query_as::<_, NoteSearchRow>(
+            r#"
+            SELECT
+                n.id, n.title, n.created_at, n.updated_at,
+                ts_rank(n.search_vector, q) AS rank,
+                ts_headline(
+                    'simple',
+                    CASE WHEN n.content = '' THEN n.title ELSE n.content END,
+                    q,
+                    'StartSel=<mark>, StopSel=</mark>, MaxFragments=2, MaxWords=30, MinWords=10'
+                ) AS snippet
+            FROM notes n, websearch_to_tsquery('simple', $2) q
+            WHERE n.user_id = $1
+                AND n.deleted_at IS NULL
+                AND n.search_vector @@ q
+                AND ($3::text IS NULL OR EXISTS (
+                    SELECT 1 FROM note_tags t WHERE t.note_id = n.id AND t.tag = $3
+                ))
+                AND ($4::timestamptz IS NULL OR n.updated_at >= $4)
+                AND ($5::timestamptz IS NULL OR n.updated_at <= $5)
+            ORDER BY rank DESC, n.updated_at DESC, n.id
+            LIMIT $6 OFFSET $7
+            "#,
         )
-        .bind(note_id)
         .bind(user_id)
-        .fetch_one(&self.db)
+        .bind(&query.q)
+        .bind(&query.tag)
+        .bind(query.from)
+        .bind(query.to)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db)
         .await?;
-        
-        self.add_note_with_tags(note_row).await
+
+        let ids: Vec<String> = rows.iter().map(|row| row.id.clone()).collect();
+        let mut tags = fetch_tags_for_notes(&self.db, &ids).await?;
+
+        Ok(rows.into_iter()
+            .map(|row| NoteSearchHit {
+                tags: tags.remove(&row.id).unwrap_or_default(),
+                id: row.id,
+                title: row.title,
+                snippet: row.snippet,
+                rank: row.rank,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+            })
+            .collect())
+    }
+
+    async fn list_note_revisions(&self, user_id: &str, note_id: &str) -> Result<Vec<NoteRevisionSummary>, SyncError> {
+        // 与读取笔记相同的权限判断, 所有者和共享接收者都可以查看历史版本
+        let note = self.get_note(user_id, note_id).await?;
+
+        let revisions = sqlx::query_as::<_, NoteRevisionSummary>(
+            r#"
+            SELECT revision, title, updated_at, archived_at
+            FROM note_revisions
+            WHERE note_id = $1
+            ORDER BY revision DESC
+            "#,
+        )
+        .bind(note_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        // 当前版本在前, 历史版本按版本号倒序
+        let current = NoteRevisionSummary {
+            revision: note.revision,
+            title: note.title,
+            updated_at: note.updated_at,
+            archived_at: None,
+        };
+        Ok(std::iter::once(current).chain(revisions).collect())
+    }
+
+    async fn get_note_revision(&self, user_id: &str, note_id: &str, revision: i64) -> Result<NoteRevision, SyncError> {
+        let note = self.get_note(user_id, note_id).await?;
+        if note.revision == revision {
+            return Ok(NoteRevision {
+                note_id: note.id,
+                revision: note.revision,
+                title: note.title,
+                content: note.content,
+                tags: note.tags,
+                updated_at: note.updated_at,
+                archived_at: None,
+            });
+        }
+
+        sqlx::query_as::<_, NoteRevision>(
+            r#"
+            SELECT note_id, revision, title, content, tags, updated_at, archived_at
+            FROM note_revisions
+            WHERE note_id = $1 AND revision = $2
+            "#,
+        )
+        .bind(note_id)
+        .bind(revision)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(SyncError::RevisionNotFound)
     }
 
-    async fn update_note(&self, user_id: &str, note_id: &str, update: NoteUpdate) -> Result<Note, SyncError> {
+    async fn restore_note_revision(&self, user_id: &str, note_id: &str, revision: i64) -> Result<(Note, Vec<NoteChange>), SyncError> {
+        let current_revision = self.get_note(user_id, note_id).await?.revision;
+        let target = self.get_note_revision(user_id, note_id, revision).await?;
+
+        // 恢复即以历史内容做一次普通更新, 产生新的版本并同步到各设备
+        // 基于读取时的版本更新, 期间被其他设备修改则返回冲突
+        let update = NoteUpdate {
+            title: Some(target.title),
+            content: Some(target.content),
+            tags: Some(target.tags.into_iter().collect()),
+            notebook_id: None,
+            base_revision: Some(current_revision),
+            updated_at: Utc::now(),
+        };
+        self.update_note(user_id, note_id, update).await
+    }
+
+    async fn list_trash(&self, user_id: &str) -> Result<Vec<TrashedNote>, SyncError> {
+        Ok(sqlx::query_as::<_, TrashedNote>(
+            r#"
+            SELECT id, title, LEFT(content, 200) AS preview, created_at, updated_at, deleted_at
+            FROM notes
+            WHERE user_id = $1 AND deleted_at IS NOT NULL
+            ORDER BY deleted_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn restore_note(&self, user_id: &str, note_id: &str) -> Result<(Note, Vec<NoteChange>), SyncError> {
         let mut tx = self.db.begin().await?;
 
-        // 更新主表
         let note_row = sqlx::query_as::<_, NoteRow>(
             r#"
             UPDATE notes
-            SET
-                title = COALESCE($1, title),
-                content = COALESCE($2, content),
-                updated_at = $3
-            WHERE id = $4 AND user_id = $5
+            SET deleted_at = NULL, updated_at = $3, revision = revision + 1
+            WHERE id = $1 AND user_id = $2 AND deleted_at IS NOT NULL
             RETURNING *
             "#,
         )
-        .bind(update.title)
-        .bind(update.content)
-        .bind(update.updated_at)
         .bind(note_id)
         .bind(user_id)
-        .fetch_one(&mut *tx)
-        .await?;
+        .bind(Utc::now())
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(SyncError::NotFound)?;
 
-        // 更新标签
-        let updated_note = if let Some(tags) = update.tags {
-            let mut note = Note {
-                id: note_row.id,
-                user_id: note_row.user_id,
-                title: note_row.title,
-                content: note_row.content,
-                tags: Vec::new(),
-                created_at: note_row.created_at,
-                updated_at: note_row.updated_at,
-            };
-            
-            // 先删除旧标签
-            sqlx::query(
-                "DELETE FROM note_tags WHERE note_id = $1"
-            )
-            .bind(note_id)
-            .execute(&mut *tx)
-            .await?;
+        // 笔记恢复后不再需要删除记录
+        sqlx::query(
+            "DELETE FROM deleted_notes WHERE note_id = $1"
+        )
+        .bind(note_id)
+        .execute(&mut *tx)
+        .await?;
 
-            // 插入新标签
-            for tag in tags.iter() {
-                note.tags.push(tag.clone());
-                sqlx::query(
-                    "INSERT INTO note_tags (note_id, tag) VALUES ($1, $2)"
-                )
-                .bind(note_id)
-                .bind(tag)
-                .execute(&mut *tx)
-                .await?;
-            }
-            note
-        } else {
-            self.add_note_with_tags(note_row).await?
-        };
+        // 对已删除该笔记的设备而言是一次重新创建
+        let mut changes = Vec::new();
+        record_change(&mut tx, user_id, note_id, ChangeKind::Created, &mut changes).await?;
+        let tags = fetch_note_tags(&mut *tx, note_id).await?;
 
         tx.commit().await?;
-        Ok(updated_note)
+        Ok((note_row.into_note(tags), changes))
     }
 
-    async fn delete_note(&self, user_id: &str, note_id: &str) -> Result<(), SyncError> {
+    async fn empty_trash(&self, user_id: &str, note_id: Option<&str>) -> Result<u64, SyncError> {
+        // 删除记录已在移入回收站时写入, 这里只需删除数据
+        let result = sqlx::query(
+            r#"
+            DELETE FROM notes
+            WHERE user_id = $1 AND deleted_at IS NOT NULL AND ($2::text IS NULL OR id = $2)
+            "#,
+        )
+        .bind(user_id)
+        .bind(note_id)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn purge_trash(&self, before: DateTime<Utc>) -> Result<u64, SyncError> {
+        let result = sqlx::query(
+            "DELETE FROM notes WHERE deleted_at IS NOT NULL AND deleted_at < $1"
+        )
+        .bind(before)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn list_tags(&self, user_id: &str) -> Result<Vec<TagSummary>, SyncError> {
+        Ok(sqlx::query_as::<_, TagSummary>(
+            r#"
+            SELECT t.tag, COUNT(*) AS note_count
+            FROM note_tags t
+            JOIN notes n ON n.id = t.note_id
+            WHERE n.user_id = $1 AND n.deleted_at IS NULL
+            GROUP BY t.tag
+            ORDER BY t.tag
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn replace_tag(&self, user_id: &str, tag: &str, new_tag: Option<&str>) -> Result<Vec<NoteChange>, SyncError> {
         let mut tx = self.db.begin().await?;
 
-        // 检查笔记是否存在
-        let exists = sqlx::query_scalar::<_, i64>(
-            "SELECT COUNT(*) FROM notes WHERE id = $1 AND user_id = $2"
+        // 锁定带有该标签的笔记
+        let note_ids = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT n.id FROM notes n
+            JOIN note_tags t ON t.note_id = n.id
+            WHERE n.user_id = $1 AND t.tag = $2 AND n.deleted_at IS NULL
+            FOR UPDATE OF n
+            "#,
         )
-        .bind(note_id)
         .bind(user_id)
-        .fetch_one(&mut *tx)
+        .bind(tag)
+        .fetch_all(&mut *tx)
         .await?;
 
-        if exists == 0 {
-            // 不存在直接返回
-            tx.commit().await?;
-            println!("note {} not exist, skip delete", note_id);
-            return Ok(());
+        if note_ids.is_empty() {
+            return Err(SyncError::TagNotFound);
+        }
+
+        archive_note_revisions(&mut tx, &note_ids).await?;
+
+        // 已有目标标签的笔记跳过插入, 即合并
+        if let Some(new_tag) = new_tag {
+            sqlx::query(
+                r#"
+                INSERT INTO note_tags (note_id, tag)
+                SELECT note_id, $2 FROM UNNEST($1::varchar[]) AS note_id
+                ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(&note_ids)
+            .bind(new_tag)
+            .execute(&mut *tx)
+            .await?;
         }
 
-        // 记录删除
         sqlx::query(
-            "INSERT INTO deleted_notes (note_id, user_id, deleted_at) VALUES ($1, $2, $3)"
+            "DELETE FROM note_tags WHERE note_id = ANY($1) AND tag = $2"
         )
-        .bind(note_id)
-        .bind(user_id)
-        .bind(Utc::now())
+        .bind(&note_ids)
+        .bind(tag)
         .execute(&mut *tx)
         .await?;
 
-        // 删除笔记
+        // 更新受影响的笔记, 使变更同步到其他设备
         sqlx::query(
-            "DELETE FROM notes WHERE id = $1 AND user_id = $2"
+            "UPDATE notes SET revision = revision + 1, updated_at = $2 WHERE id = ANY($1)"
         )
-        .bind(note_id)
-        .bind(user_id)
+        .bind(&note_ids)
+        .bind(Utc::now())
         .execute(&mut *tx)
         .await?;
 
+        let mut user_ids = vec![user_id.to_string()];
+        for note_id in note_ids.iter() {
+            user_ids.extend(note_audience(&mut *tx, note_id).await?);
+        }
+        lock_sync_states(&mut tx, user_ids).await?;
+
+        let mut changes = Vec::new();
+        for note_id in note_ids.iter() {
+            record_change(&mut tx, user_id, note_id, ChangeKind::Updated, &mut changes).await?;
+        }
+
         tx.commit().await?;
-        println!("delete note db success");
-        Ok(())
+        Ok(changes)
     }
 
-    async fn get_sync_notes(&self, user_id: &str, time: DateTime<Utc>) -> Result<(Vec<Note>, Vec<String>), SyncError> {
-        //  获取变更的笔记
-        let notes = sqlx::query_as::<_, NoteRow>(
+    async fn export_notes(&self, user_id: &str) -> Result<NotesExport, SyncError> {
+        let mut tx = self.db.begin().await?;
+
+        let notebooks = sqlx::query_as::<_, Notebook>(
+            "SELECT * FROM notebooks WHERE user_id = $1 ORDER BY created_at, id"
+        )
+        .bind(user_id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let note_rows = sqlx::query_as::<_, NoteRow>(
+            "SELECT * FROM notes WHERE user_id = $1 ORDER BY created_at, id"
+        )
+        .bind(user_id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let note_ids: Vec<String> = note_rows.iter().map(|row| row.id.clone()).collect();
+        let mut tags = fetch_tags_for_notes(&mut *tx, &note_ids).await?;
+
+        let revisions = sqlx::query_as::<_, NoteRevision>(
             r#"
-            SELECT n.* FROM notes n
-            WHERE n.user_id = $1 AND n.updated_at > $2
-            ORDER BY n.updated_at ASC
+            SELECT note_id, revision, title, content, tags, updated_at, archived_at
+            FROM note_revisions
+            WHERE user_id = $1
+            ORDER BY note_id, revision
             "#,
         )
         .bind(user_id)
-        .bind(time)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        let mut notes = Vec::new();
+        let mut trash = Vec::new();
+        for row in note_rows {
+            let deleted_at = row.deleted_at;
+            let note_tags = tags.remove(&row.id).unwrap_or_default();
+            let note = row.into_note(note_tags);
+            match deleted_at {
+                Some(deleted_at) => trash.push(ExportedTrashNote { note, deleted_at }),
+                None => notes.push(note),
+            }
+        }
+
+        Ok(NotesExport { notebooks, notes, trash, revisions })
+    }
+
+    async fn list_notebooks(&self, user_id: &str) -> Result<Vec<Notebook>, SyncError> {
+        Ok(sqlx::query_as::<_, Notebook>(
+            "SELECT * FROM notebooks WHERE user_id = $1 ORDER BY name, id"
+        )
+        .bind(user_id)
         .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn get_notebook(&self, user_id: &str, notebook_id: &str) -> Result<Notebook, SyncError> {
+        sqlx::query_as::<_, Notebook>(
+            "SELECT * FROM notebooks WHERE id = $1 AND user_id = $2"
+        )
+        .bind(notebook_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(SyncError::NotebookNotFound)
+    }
+
+    async fn create_notebook(&self, user_id: &str, notebook_id: &str, notebook: &NotebookCreate) -> Result<(Notebook, Vec<NoteChange>), SyncError> {
+        let mut tx = self.db.begin().await?;
+
+        if !notebook_exists(&mut tx, user_id, notebook.parent_id.as_deref()).await? {
+            return Err(SyncError::NotebookNotFound);
+        }
+
+        let created = sqlx::query_as::<_, Notebook>(
+            r#"
+            INSERT INTO notebooks (id, user_id, parent_id, name, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $5)
+            ON CONFLICT (id) DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(notebook_id)
+        .bind(user_id)
+        .bind(&notebook.parent_id)
+        .bind(&notebook.name)
+        .bind(notebook.created_at)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| SyncError::InvalidRequest("Notebook already exists".to_string()))?;
+
+        let mut changes = Vec::new();
+        record_notebook_change(&mut tx, user_id, notebook_id, ChangeKind::Created, &mut changes).await?;
+        tx.commit().await?;
+        Ok((created, changes))
+    }
+
+    async fn update_notebook(&self, user_id: &str, notebook_id: &str, name: Option<&str>, parent_id: Option<Option<&str>>, base_revision: Option<i64>, updated_at: DateTime<Utc>) -> Result<(Notebook, Vec<NoteChange>), SyncError> {
+        let mut tx = self.db.begin().await?;
+        lock_notebooks(&mut tx, user_id).await?;
+
+        let current = sqlx::query_as::<_, Notebook>(
+            "SELECT * FROM notebooks WHERE id = $1 AND user_id = $2 FOR UPDATE"
+        )
+        .bind(notebook_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(SyncError::NotebookNotFound)?;
+
+        if let Some(base_revision) = base_revision
+            && base_revision != current.revision
+        {
+            return Err(SyncError::NotebookConflict(Box::new(current)));
+        }
+
+        // 移动可能改变共享范围, 记录移动前可以访问的用户
+        let previous_audience = match parent_id {
+            Some(_) => notebook_audience(&mut tx, notebook_id).await?,
+            None => Vec::new(),
+        };
+
+        if let Some(Some(parent_id)) = parent_id {
+            if !notebook_exists(&mut tx, user_id, Some(parent_id)).await? {
+                return Err(SyncError::NotebookNotFound);
+            }
+
+            // 新的父笔记本不能是自身或其子孙
+            let creates_cycle = sqlx::query_scalar::<_, bool>(
+                r#"
+                WITH RECURSIVE ancestors AS (
+                    SELECT id, parent_id FROM notebooks WHERE id = $1
+                    UNION ALL
+                    SELECT n.id, n.parent_id FROM notebooks n JOIN ancestors a ON n.id = a.parent_id
+                )
+                SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = $2)
+                "#,
+            )
+            .bind(parent_id)
+            .bind(notebook_id)
+            .fetch_one(&mut *tx)
+            .await?;
+            if creates_cycle {
+                return Err(SyncError::InvalidRequest("Cannot move a notebook into itself or its descendants".to_string()));
+            }
+        }
+
+        let updated = sqlx::query_as::<_, Notebook>(
+            r#"
+            UPDATE notebooks
+            SET
+                name = COALESCE($3, name),
+                parent_id = CASE WHEN $4 THEN $5 ELSE parent_id END,
+                updated_at = $6,
+                revision = revision + 1
+            WHERE id = $1 AND user_id = $2
+            RETURNING *
+            "#,
+        )
+        .bind(notebook_id)
+        .bind(user_id)
+        .bind(name)
+        .bind(parent_id.is_some())
+        .bind(parent_id.flatten())
+        .bind(updated_at)
+        .fetch_one(&mut *tx)
         .await?;
 
-        // 获取笔记标签
-        let mut notes_with_tags = Vec::new();
-        for note_row in notes {
-            let note = self.add_note_with_tags(note_row).await?;
-            notes_with_tags.push(note);
+        let mut changes = Vec::new();
+        if parent_id.is_none() {
+            record_notebook_change(&mut tx, user_id, notebook_id, ChangeKind::Updated, &mut changes).await?;
+        } else {
+            let audience = notebook_audience(&mut tx, notebook_id).await?;
+            let moved_users = symmetric_difference(&previous_audience, &audience);
+            lock_sync_states(&mut tx, [vec![user_id.to_string()], previous_audience, audience].concat()).await?;
+            record_notebook_change(&mut tx, user_id, notebook_id, ChangeKind::Updated, &mut changes).await?;
+
+            // 移出共享范围的用户删除整棵子树, 移入的用户获得整棵子树
+            let (notebook_ids, note_ids) = shared_entities(&mut tx, None, Some(notebook_id)).await?;
+            for recipient_id in moved_users {
+                record_access_changes(&mut tx, &recipient_id, &notebook_ids, &note_ids, &mut changes).await?;
+            }
         }
 
-        // 获取删除的笔记ID
-        let deleted_note_ids = sqlx::query_scalar::<_, String>(
+        tx.commit().await?;
+        Ok((updated, changes))
+    }
+
+    async fn delete_notebook(&self, user_id: &str, notebook_id: &str) -> Result<Vec<NoteChange>, SyncError> {
+        let mut tx = self.db.begin().await?;
+        lock_notebooks(&mut tx, user_id).await?;
+
+        let notebook_ids = sqlx::query_scalar::<_, String>(
             r#"
-            SELECT note_id FROM deleted_notes
-            WHERE user_id = $1 AND deleted_at > $2
+            WITH RECURSIVE tree AS (
+                SELECT id FROM notebooks WHERE id = $1 AND user_id = $2
+                UNION ALL
+                SELECT n.id FROM notebooks n JOIN tree t ON n.parent_id = t.id
+            )
+            SELECT id FROM tree
             "#,
         )
+        .bind(notebook_id)
         .bind(user_id)
-        .bind(time)
-        .fetch_all(&self.db)
+        .fetch_all(&mut *tx)
         .await?;
-        
-        Ok((notes_with_tags, deleted_note_ids))
+
+        if notebook_ids.is_empty() {
+            return Err(SyncError::NotebookNotFound);
+        }
+
+        // 笔记先移入回收站, 删除笔记本后其 notebook_id 由外键置空, 恢复时回到顶层
+        let note_ids = sqlx::query_scalar::<_, String>(
+            "SELECT id FROM notes WHERE user_id = $1 AND notebook_id = ANY($2) AND deleted_at IS NULL"
+        )
+        .bind(user_id)
+        .bind(&notebook_ids)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut user_ids = notebook_audience(&mut tx, notebook_id).await?;
+        user_ids.extend(share_recipients(&mut tx, &notebook_ids, &note_ids).await?);
+        user_ids.push(user_id.to_string());
+        lock_sync_states(&mut tx, user_ids).await?;
+
+        let mut changes = Vec::new();
+        for note_id in note_ids.iter() {
+            remove_note(&mut tx, user_id, note_id, &mut changes).await?;
+        }
+
+        // 删除前记录, 以便找到共享这些笔记本的用户, 相关的共享随笔记本级联删除
+        for notebook_id in notebook_ids.iter() {
+            record_notebook_change(&mut tx, user_id, notebook_id, ChangeKind::Deleted, &mut changes).await?;
+        }
+
+        sqlx::query("DELETE FROM notebooks WHERE id = ANY($1)")
+            .bind(&notebook_ids)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(changes)
+    }
+
+    async fn create_share(&self, owner_id: &str, share_id: &str, recipient_email: &str, note_id: Option<&str>, notebook_id: Option<&str>, permission: SharePermission) -> Result<(Share, Vec<NoteChange>), SyncError> {
+        let mut tx = self.db.begin().await?;
+
+        let recipient_id = sqlx::query_scalar::<_, String>(
+            "SELECT id FROM users WHERE lower(email) = lower($1)"
+        )
+        .bind(recipient_email)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(SyncError::UserNotFound)?;
+
+        if recipient_id == owner_id {
+            return Err(SyncError::InvalidRequest("Cannot share with yourself".to_string()));
+        }
+
+        // 只能共享自己的笔记或笔记本
+        let target_column = if let Some(note_id) = note_id {
+            let owned = sqlx::query_scalar::<_, i32>(
+                "SELECT 1 FROM notes WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL FOR SHARE"
+            )
+            .bind(note_id)
+            .bind(owner_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            if owned.is_none() {
+                return Err(SyncError::NotFound);
+            }
+            "note_id"
+        } else {
+            if !notebook_exists(&mut tx, owner_id, notebook_id).await? {
+                return Err(SyncError::NotebookNotFound);
+            }
+            "notebook_id"
+        };
+
+        let share_id = sqlx::query_scalar::<_, String>(&format!(
+            r#"
+            INSERT INTO shares (id, owner_id, recipient_id, note_id, notebook_id, permission, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (recipient_id, {target_column}) DO UPDATE SET permission = EXCLUDED.permission
+            RETURNING id
+            "#,
+        ))
+        .bind(share_id)
+        .bind(owner_id)
+        .bind(&recipient_id)
+        .bind(note_id)
+        .bind(notebook_id)
+        .bind(permission)
+        .bind(Utc::now())
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // 共享的内容写入接收者的变更日志, 出现在其同步结果中
+        let mut changes = Vec::new();
+        let (notebook_ids, note_ids) = shared_entities(&mut tx, note_id, notebook_id).await?;
+        record_access_changes(&mut tx, &recipient_id, &notebook_ids, &note_ids, &mut changes).await?;
+
+        let share = sqlx::query_as::<_, Share>(&format!("{SHARE_SELECT} WHERE s.id = $1"))
+            .bind(&share_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok((share, changes))
+    }
+
+    async fn list_shares(&self, owner_id: &str) -> Result<Vec<Share>, SyncError> {
+        Ok(sqlx::query_as::<_, Share>(&format!("{SHARE_SELECT} WHERE s.owner_id = $1 ORDER BY s.created_at DESC"))
+            .bind(owner_id)
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    async fn list_received_shares(&self, recipient_id: &str) -> Result<Vec<Share>, SyncError> {
+        Ok(sqlx::query_as::<_, Share>(&format!("{SHARE_SELECT} WHERE s.recipient_id = $1 ORDER BY s.created_at DESC"))
+            .bind(recipient_id)
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    async fn delete_share(&self, user_id: &str, share_id: &str) -> Result<Vec<NoteChange>, SyncError> {
+        let mut tx = self.db.begin().await?;
+
+        let (recipient_id, note_id, notebook_id) = sqlx::query_as::<_, (String, Option<String>, Option<String>)>(
+            r#"
+            DELETE FROM shares WHERE id = $1 AND (owner_id = $2 OR recipient_id = $2)
+            RETURNING recipient_id, note_id, notebook_id
+            "#,
+        )
+        .bind(share_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(SyncError::ShareNotFound)?;
+
+        // 接收者不再能访问的内容作为删除下发, 通过其他共享仍可访问的保持不变
+        let mut changes = Vec::new();
+        let (notebook_ids, note_ids) = shared_entities(&mut tx, note_id.as_deref(), notebook_id.as_deref()).await?;
+        record_access_changes(&mut tx, &recipient_id, &notebook_ids, &note_ids, &mut changes).await?;
+
+        tx.commit().await?;
+        Ok(changes)
     }
 }
 
 impl Database {
     async fn add_note_with_tags(&self, note_row: NoteRow) -> Result<Note, SyncError> {
-        let tags = sqlx::query_scalar::<_, String>(
-            "SELECT tag FROM note_tags WHERE note_id = $1"
+        let tags = fetch_note_tags(&self.db, &note_row.id).await?;
+        Ok(note_row.into_note(tags))
+    }
+}
+
+// 以下函数在调用方的事务内执行
+
+async fn insert_note(conn: &mut PgConnection, user_id: &str, note_id: &str, note: &NoteCreate, changes: &mut Vec<NoteChange>) -> Result<(), SyncError> {
+    if !notebook_exists(conn, user_id, note.notebook_id.as_deref()).await? {
+        return Err(SyncError::NotebookNotFound);
+    }
+
+    // 插入主表
+    sqlx::query(
+        r#"
+        INSERT INTO notes (id, user_id, title, content, created_at, updated_at, notebook_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(note_id)
+    .bind(user_id)
+    .bind(&note.title)
+    .bind("")
+    .bind(note.created_at)
+    .bind(note.created_at)
+    .bind(&note.notebook_id)
+    .execute(&mut *conn)
+    .await?;
+
+    record_change(conn, user_id, note_id, ChangeKind::Created, changes).await?;
+    Ok(())
+}
+
+async fn upsert_note(conn: &mut PgConnection, user_id: &str, note_id: &str, note: &NoteImport, changes: &mut Vec<NoteChange>) -> Result<(), SyncError> {
+    if !notebook_exists(conn, user_id, note.notebook_id.as_deref()).await? {
+        return Err(SyncError::NotebookNotFound);
+    }
+
+    // 锁定已有笔记, 包括回收站中的, 只有所有者可以覆盖
+    let owner_id = sqlx::query_scalar::<_, String>(
+        "SELECT user_id FROM notes WHERE id = $1 FOR UPDATE"
+    )
+    .bind(note_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if owner_id.is_some_and(|owner_id| owner_id != user_id) {
+        return Err(SyncError::Forbidden);
+    }
+
+    // 覆盖前保存已有笔记的历史版本
+    archive_note_revisions(conn, &[note_id.to_string()]).await?;
+
+    // 插入主表, xmax 为 0 表示新插入的行
+    let inserted = sqlx::query_scalar::<_, bool>(
+        r#"
+        INSERT INTO notes (id, user_id, title, content, created_at, updated_at, notebook_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (id) DO UPDATE SET
+            notebook_id = EXCLUDED.notebook_id,
+            title = EXCLUDED.title,
+            content = EXCLUDED.content,
+            created_at = EXCLUDED.created_at,
+            updated_at = EXCLUDED.updated_at,
+            revision = notes.revision + 1,
+            deleted_at = NULL
+        WHERE notes.user_id = EXCLUDED.user_id
+        RETURNING (xmax = 0)
+        "#,
+    )
+    .bind(note_id)
+    .bind(user_id)
+    .bind(&note.title)
+    .bind(&note.content)
+    .bind(note.created_at)
+    .bind(note.updated_at)
+    .bind(&note.notebook_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    // 并发插入的同 id 笔记属于其他用户
+    .ok_or(SyncError::Forbidden)?;
+
+    replace_note_tags(conn, note_id, &note.tags).await?;
+    let kind = if inserted { ChangeKind::Created } else { ChangeKind::Updated };
+    record_change(conn, user_id, note_id, kind, changes).await?;
+    Ok(())
+}
+
+async fn apply_note_update(conn: &mut PgConnection, user_id: &str, note_id: &str, update: NoteUpdate, changes: &mut Vec<NoteChange>) -> Result<Note, SyncError> {
+    // 锁定当前笔记, 检查权限和版本是否冲突
+    let (current, permission) = lock_note(conn, user_id, note_id).await?;
+    if permission < SharePermission::Write {
+        return Err(SyncError::Forbidden);
+    }
+    // 共享的笔记按所有者更新, 笔记本也必须属于所有者
+    let owner_id = current.user_id.clone();
+    // 移动会改变共享范围, 只有所有者可以移动
+    if update.notebook_id.is_some() && owner_id != user_id {
+        return Err(SyncError::Forbidden);
+    }
+
+    if let Some(base_revision) = update.base_revision
+        && base_revision != current.revision
+    {
+        let tags = fetch_note_tags(&mut *conn, note_id).await?;
+        return Err(SyncError::Conflict(Box::new(current.into_note(tags))));
+    }
+
+    if let Some(notebook_id) = &update.notebook_id
+        && !notebook_exists(conn, &owner_id, notebook_id.as_deref()).await?
+    {
+        return Err(SyncError::NotebookNotFound);
+    }
+
+    // 移动可能改变共享范围, 记录移动前可以访问的用户
+    let moved = update.notebook_id.is_some();
+    let previous_audience = if moved {
+        note_audience(&mut *conn, note_id).await?
+    } else {
+        Vec::new()
+    };
+
+    archive_note_revisions(conn, &[note_id.to_string()]).await?;
+
+    // 更新主表
+    let note_row = sqlx::query_as::<_, NoteRow>(
+        r#"
+        UPDATE notes
+        SET
+            title = COALESCE($1, title),
+            content = COALESCE($2, content),
+            updated_at = $3,
+            revision = revision + 1,
+            notebook_id = CASE WHEN $6 THEN $7 ELSE notebook_id END
+        WHERE id = $4 AND user_id = $5
+        RETURNING *
+        "#,
+    )
+    .bind(update.title)
+    .bind(update.content)
+    .bind(update.updated_at)
+    .bind(note_id)
+    .bind(&owner_id)
+    .bind(update.notebook_id.is_some())
+    .bind(update.notebook_id.flatten())
+    .fetch_one(&mut *conn)
+    .await?;
+
+    // 更新标签
+    let tags = if let Some(tags) = update.tags {
+        replace_note_tags(conn, note_id, &tags).await?;
+        tags.into_iter().collect()
+    } else {
+        fetch_note_tags(&mut *conn, note_id).await?
+    };
+
+    if !moved {
+        record_change(conn, &owner_id, note_id, ChangeKind::Updated, changes).await?;
+    } else {
+        let audience = note_audience(&mut *conn, note_id).await?;
+        let moved_users = symmetric_difference(&previous_audience, &audience);
+        lock_sync_states(conn, [vec![owner_id.clone()], previous_audience, audience].concat()).await?;
+        record_change(conn, &owner_id, note_id, ChangeKind::Updated, changes).await?;
+
+        // 移出共享范围的用户收到删除
+        for recipient_id in moved_users {
+            record_access_changes(conn, &recipient_id, &[], &[note_id.to_string()], changes).await?;
+        }
+    }
+
+    Ok(note_row.into_note(tags))
+}
+
+// 批量同步中的更新, 冲突和不存在转为单个操作的结果
+async fn apply_update_operation(conn: &mut PgConnection, user_id: &str, note_id: String, update: NoteUpdate, changes: &mut Vec<NoteChange>) -> Result<SyncOperationResult, SyncError> {
+    match apply_note_update(conn, user_id, &note_id, update, changes).await {
+        Ok(note) => Ok(SyncOperationResult::accepted(note_id, Some(note))),
+        Err(SyncError::Conflict(note)) => Ok(SyncOperationResult::conflict(note_id, *note)),
+        Err(SyncError::NotFound) => Ok(SyncOperationResult::rejected(note_id, "Note not found")),
+        Err(SyncError::NotebookNotFound) => Ok(SyncOperationResult::rejected(note_id, "Notebook not found")),
+        Err(SyncError::Forbidden) => Ok(SyncOperationResult::rejected(note_id, "Permission denied")),
+        Err(e) => Err(e),
+    }
+}
+
+async fn remove_note(conn: &mut PgConnection, user_id: &str, note_id: &str, changes: &mut Vec<NoteChange>) -> Result<(), SyncError> {
+    // 检查笔记是否存在, 已在回收站中的视为不存在
+    let (current, permission) = match lock_note(conn, user_id, note_id).await {
+        Ok(note) => note,
+        Err(SyncError::NotFound) => {
+            // 不存在直接返回
+            tracing::debug!(note_id, "Note not found, skip delete");
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    if permission < SharePermission::Write {
+        return Err(SyncError::Forbidden);
+    }
+    // 共享的笔记移入所有者的回收站
+    let user_id = current.user_id.as_str();
+
+    // 记录删除
+    sqlx::query(
+        r#"
+        INSERT INTO deleted_notes (note_id, user_id, deleted_at) VALUES ($1, $2, $3)
+        ON CONFLICT (note_id) DO UPDATE SET deleted_at = EXCLUDED.deleted_at
+        "#,
+    )
+    .bind(note_id)
+    .bind(user_id)
+    .bind(Utc::now())
+    .execute(&mut *conn)
+    .await?;
+
+    // 移入回收站
+    sqlx::query(
+        "UPDATE notes SET deleted_at = $3 WHERE id = $1 AND user_id = $2"
+    )
+    .bind(note_id)
+    .bind(user_id)
+    .bind(Utc::now())
+    .execute(&mut *conn)
+    .await?;
+
+    record_change(conn, user_id, note_id, ChangeKind::Deleted, changes).await?;
+    tracing::debug!(note_id, "Note deleted");
+    Ok(())
+}
+
+// 变更同时写入所有者和共享用户的变更日志, 按用户 id 顺序写入使序号行按固定顺序加锁
+async fn record_change(conn: &mut PgConnection, user_id: &str, note_id: &str, kind: ChangeKind, changes: &mut Vec<NoteChange>) -> Result<(), SyncError> {
+    let audience = note_audience(&mut *conn, note_id).await?;
+    for user_id in with_owner(user_id, audience) {
+        record_entity_change(conn, &user_id, ChangeEntity::Note, note_id, kind, changes).await?;
+    }
+    Ok(())
+}
+
+async fn record_notebook_change(conn: &mut PgConnection, user_id: &str, notebook_id: &str, kind: ChangeKind, changes: &mut Vec<NoteChange>) -> Result<(), SyncError> {
+    let audience = notebook_audience(&mut *conn, notebook_id).await?;
+    for user_id in with_owner(user_id, audience) {
+        record_entity_change(conn, &user_id, ChangeEntity::Notebook, notebook_id, kind, changes).await?;
+    }
+    Ok(())
+}
+
+fn with_owner(owner_id: &str, mut audience: Vec<String>) -> Vec<String> {
+    audience.push(owner_id.to_string());
+    audience.sort();
+    audience.dedup();
+    audience
+}
+
+// 一个事务写入多个实体的变更时, 先按 id 顺序锁定所有相关用户的序号行
+// 否则各次写入的用户集合不同, 与其他事务交叉加锁会死锁
+async fn lock_sync_states(conn: &mut PgConnection, mut user_ids: Vec<String>) -> Result<(), sqlx::Error> {
+    user_ids.sort();
+    user_ids.dedup();
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_sync_state (user_id, last_seq)
+        SELECT user_id, 0 FROM UNNEST($1::varchar[]) AS user_id
+        ON CONFLICT (user_id) DO NOTHING
+        "#,
+    )
+    .bind(&user_ids)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("SELECT 1 FROM user_sync_state WHERE user_id = ANY($1) ORDER BY user_id FOR UPDATE")
+        .bind(&user_ids)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+// 分配用户的下一个变更序号并写入变更日志
+// user_sync_state 的行锁持有到事务提交, 同一用户的写操作按序号顺序提交
+async fn record_entity_change(conn: &mut PgConnection, user_id: &str, entity: ChangeEntity, entity_id: &str, kind: ChangeKind, changes: &mut Vec<NoteChange>) -> Result<(), sqlx::Error> {
+    let seq = sqlx::query_scalar::<_, i64>(
+        r#"
+        INSERT INTO user_sync_state (user_id, last_seq) VALUES ($1, 1)
+        ON CONFLICT (user_id) DO UPDATE SET last_seq = user_sync_state.last_seq + 1
+        RETURNING last_seq
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let change = sqlx::query_as::<_, NoteChange>(
+        r#"
+        INSERT INTO note_changes (user_id, seq, entity, note_id, kind, changed_at) VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING *
+        "#,
+    )
+    .bind(user_id)
+    .bind(seq)
+    .bind(entity)
+    .bind(entity_id)
+    .bind(kind)
+    .bind(Utc::now())
+    .fetch_one(&mut *conn)
+    .await?;
+
+    changes.push(change);
+    Ok(())
+}
+
+// 保存笔记当前状态为历史版本
+async fn archive_note_revisions(conn: &mut PgConnection, note_ids: &[String]) -> Result<(), SyncError> {
+    sqlx::query(
+        r#"
+        INSERT INTO note_revisions (note_id, revision, user_id, title, content, tags, updated_at, archived_at)
+        SELECT
+            n.id, n.revision, n.user_id, n.title, n.content,
+            COALESCE((SELECT array_agg(t.tag ORDER BY t.tag) FROM note_tags t WHERE t.note_id = n.id), '{}'),
+            n.updated_at, $2
+        FROM notes n
+        WHERE n.id = ANY($1)
+        ON CONFLICT (note_id, revision) DO NOTHING
+        "#,
+    )
+    .bind(note_ids)
+    .bind(Utc::now())
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn replace_note_tags(conn: &mut PgConnection, note_id: &str, tags: &HashSet<String>) -> Result<(), SyncError> {
+    // 先删除旧标签
+    sqlx::query(
+        "DELETE FROM note_tags WHERE note_id = $1"
+    )
+    .bind(note_id)
+    .execute(&mut *conn)
+    .await?;
+
+    // 插入新标签
+    for tag in tags.iter() {
+        sqlx::query(
+            "INSERT INTO note_tags (note_id, tag) VALUES ($1, $2)"
         )
-        .bind(&note_row.id)
-        .fetch_all(&self.db)
+        .bind(note_id)
+        .bind(tag)
+        .execute(&mut *conn)
         .await?;
-        
-        Ok(Note {
-            id: note_row.id,
-            user_id: note_row.user_id,
-            title: note_row.title,
-            content: note_row.content,
-            tags,
-            created_at: note_row.created_at,
-            updated_at: note_row.updated_at,
-        })
     }
+
+    Ok(())
+}
+
+async fn note_exists(conn: &mut PgConnection, note_id: &str) -> Result<bool, SyncError> {
+    Ok(sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM notes WHERE id = $1)"
+    )
+    .bind(note_id)
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+// 检查笔记本属于该用户并加共享锁, 防止并发删除, 为空表示不归类
+async fn notebook_exists(conn: &mut PgConnection, user_id: &str, notebook_id: Option<&str>) -> Result<bool, SyncError> {
+    let Some(notebook_id) = notebook_id else {
+        return Ok(true);
+    };
+
+    Ok(sqlx::query_scalar::<_, i32>(
+        "SELECT 1 FROM notebooks WHERE id = $1 AND user_id = $2 FOR SHARE"
+    )
+    .bind(notebook_id)
+    .bind(user_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .is_some())
+}
+
+// 同一用户的笔记本移动和删除串行执行, 避免并发移动形成环
+async fn lock_notebooks(conn: &mut PgConnection, user_id: &str) -> Result<(), SyncError> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('notebooks:' || $1))")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+// 锁定用户可以访问的笔记, 返回笔记和用户的权限, 所有者拥有写权限
+async fn lock_note(conn: &mut PgConnection, user_id: &str, note_id: &str) -> Result<(NoteRow, SharePermission), SyncError> {
+    let note_row = sqlx::query_as::<_, NoteRow>(
+        "SELECT * FROM notes WHERE id = $1 AND deleted_at IS NULL FOR UPDATE"
+    )
+    .bind(note_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(SyncError::NotFound)?;
+
+    if note_row.user_id == user_id {
+        return Ok((note_row, SharePermission::Write));
+    }
+    let permission = note_permission(&mut *conn, user_id, note_id).await?.ok_or(SyncError::NotFound)?;
+    Ok((note_row, permission))
+}
+
+// 用户通过直接共享或上级笔记本共享获得的最高权限
+async fn note_permission<'e, E: PgExecutor<'e>>(executor: E, user_id: &str, note_id: &str) -> Result<Option<SharePermission>, SyncError> {
+    let permissions = sqlx::query_scalar::<_, SharePermission>(&format!(
+        r#"
+        {NOTE_ANCESTORS}
+        SELECT permission FROM shares
+        WHERE recipient_id = $2 AND (note_id = $1 OR notebook_id IN (SELECT id FROM ancestors))
+        "#,
+    ))
+    .bind(note_id)
+    .bind(user_id)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(permissions.into_iter().max())
+}
+
+// 笔记及其所在笔记本共享给的用户
+async fn note_audience<'e, E: PgExecutor<'e>>(executor: E, note_id: &str) -> Result<Vec<String>, SyncError> {
+    Ok(sqlx::query_scalar::<_, String>(&format!(
+        r#"
+        {NOTE_ANCESTORS}
+        SELECT DISTINCT recipient_id FROM shares
+        WHERE note_id = $1 OR notebook_id IN (SELECT id FROM ancestors)
+        ORDER BY recipient_id
+        "#,
+    ))
+    .bind(note_id)
+    .fetch_all(executor)
+    .await?)
+}
+
+// 删除账号前撤销其全部共享, 接收者失去访问的内容作为删除写入其变更日志
+// 账号删除时共享随外键级联删除, 不会留下记录; 这里不实时推送, 接收者下次同步时收到
+pub(super) async fn revoke_owner_shares(conn: &mut PgConnection, owner_id: &str) -> Result<(), sqlx::Error> {
+    let shares = sqlx::query_as::<_, (String, Option<String>, Option<String>)>(
+        "DELETE FROM shares WHERE owner_id = $1 RETURNING recipient_id, note_id, notebook_id"
+    )
+    .bind(owner_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    lock_sync_states(conn, shares.iter().map(|(recipient_id, _, _)| recipient_id.clone()).collect()).await?;
+
+    let mut changes = Vec::new();
+    for (recipient_id, note_id, notebook_id) in shares {
+        let (notebook_ids, note_ids) = shared_entities(conn, note_id.as_deref(), notebook_id.as_deref()).await?;
+        record_access_changes(conn, &recipient_id, &notebook_ids, &note_ids, &mut changes).await?;
+    }
+    Ok(())
+}
+
+// 直接共享这些笔记本或笔记的用户
+async fn share_recipients(conn: &mut PgConnection, notebook_ids: &[String], note_ids: &[String]) -> Result<Vec<String>, SyncError> {
+    Ok(sqlx::query_scalar::<_, String>(
+        "SELECT DISTINCT recipient_id FROM shares WHERE notebook_id = ANY($1) OR note_id = ANY($2)"
+    )
+    .bind(notebook_ids)
+    .bind(note_ids)
+    .fetch_all(&mut *conn)
+    .await?)
+}
+
+// 笔记本及其上级笔记本共享给的用户
+async fn notebook_audience(conn: &mut PgConnection, notebook_id: &str) -> Result<Vec<String>, SyncError> {
+    Ok(sqlx::query_scalar::<_, String>(
+        r#"
+        WITH RECURSIVE ancestors AS (
+            SELECT id, parent_id FROM notebooks WHERE id = $1
+            UNION ALL
+            SELECT nb.id, nb.parent_id FROM notebooks nb JOIN ancestors a ON nb.id = a.parent_id
+        )
+        SELECT DISTINCT recipient_id FROM shares
+        WHERE notebook_id IN (SELECT id FROM ancestors)
+        ORDER BY recipient_id
+        "#,
+    )
+    .bind(notebook_id)
+    .fetch_all(&mut *conn)
+    .await?)
+}
+
+// 共享包含的笔记本和笔记, 共享笔记本时包括子笔记本及其中未删除的笔记
+async fn shared_entities(conn: &mut PgConnection, note_id: Option<&str>, notebook_id: Option<&str>) -> Result<(Vec<String>, Vec<String>), sqlx::Error> {
+    let Some(notebook_id) = notebook_id else {
+        return Ok((Vec::new(), note_id.map(str::to_string).into_iter().collect()));
+    };
+
+    let notebook_ids = sqlx::query_scalar::<_, String>(
+        r#"
+        WITH RECURSIVE tree AS (
+            SELECT id FROM notebooks WHERE id = $1
+            UNION ALL
+            SELECT n.id FROM notebooks n JOIN tree t ON n.parent_id = t.id
+        )
+        SELECT id FROM tree
+        "#,
+    )
+    .bind(notebook_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let note_ids = sqlx::query_scalar::<_, String>(
+        "SELECT id FROM notes WHERE notebook_id = ANY($1) AND deleted_at IS NULL"
+    )
+    .bind(&notebook_ids)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok((notebook_ids, note_ids))
+}
+
+// 共享范围变化后按用户当前能否访问写入更新或删除, 笔记本先于其中的笔记下发
+async fn record_access_changes(conn: &mut PgConnection, user_id: &str, notebook_ids: &[String], note_ids: &[String], changes: &mut Vec<NoteChange>) -> Result<(), sqlx::Error> {
+    let visible_notebooks: HashSet<String> = sqlx::query_scalar::<_, String>(&format!(
+        r#"
+        {SHARED_NOTEBOOKS}
+        SELECT id FROM notebooks
+        WHERE id = ANY($2) AND (user_id = $1 OR id IN (SELECT id FROM shared_notebooks))
+        "#,
+    ))
+    .bind(user_id)
+    .bind(notebook_ids)
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .collect();
+
+    let visible_notes: HashSet<String> = sqlx::query_scalar::<_, String>(&format!(
+        r#"
+        {SHARED_NOTEBOOKS}
+        SELECT n.id FROM notes n
+        WHERE n.id = ANY($2) AND n.deleted_at IS NULL AND {ACCESSIBLE_NOTE}
+        "#,
+    ))
+    .bind(user_id)
+    .bind(note_ids)
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .collect();
+
+    for notebook_id in notebook_ids {
+        let kind = if visible_notebooks.contains(notebook_id) { ChangeKind::Updated } else { ChangeKind::Deleted };
+        record_entity_change(conn, user_id, ChangeEntity::Notebook, notebook_id, kind, changes).await?;
+    }
+    for note_id in note_ids {
+        let kind = if visible_notes.contains(note_id) { ChangeKind::Updated } else { ChangeKind::Deleted };
+        record_entity_change(conn, user_id, ChangeEntity::Note, note_id, kind, changes).await?;
+    }
+    Ok(())
+}
+
+// 只在其中一侧出现的用户
+fn symmetric_difference(before: &[String], after: &[String]) -> Vec<String> {
+    let mut users: Vec<String> = before.iter().filter(|id| !after.contains(id))
+        .chain(after.iter().filter(|id| !before.contains(id)))
+        .cloned()
+        .collect();
+    users.sort();
+    users
+}
+
+async fn fetch_note(conn: &mut PgConnection, user_id: &str, note_id: &str) -> Result<Note, SyncError> {
+    let note_row = sqlx::query_as::<_, NoteRow>(
+        "SELECT * FROM notes WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL"
+    )
+    .bind(note_id)
+    .bind(user_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(SyncError::NotFound)?;
+
+    let tags = fetch_note_tags(&mut *conn, note_id).await?;
+    Ok(note_row.into_note(tags))
+}
+
+// 批量获取多个笔记的标签
+async fn fetch_tags_for_notes<'e, E: PgExecutor<'e>>(executor: E, note_ids: &[String]) -> Result<HashMap<String, Vec<String>>, SyncError> {
+    let rows = sqlx::query_as::<_, (String, String)>(
+        "SELECT note_id, tag FROM note_tags WHERE note_id = ANY($1)"
+    )
+    .bind(note_ids)
+    .fetch_all(executor)
+    .await?;
+
+    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+    for (note_id, tag) in rows {
+        tags.entry(note_id).or_default().push(tag);
+    }
+    Ok(tags)
+}
+
+// 获取笔记标签, 可在事务内使用
+async fn fetch_note_tags<'e, E: PgExecutor<'e>>(executor: E, note_id: &str) -> Result<Vec<String>, SyncError> {
+    Ok(sqlx::query_scalar::<_, String>(
+        "SELECT tag FROM note_tags WHERE note_id = $1"
+    )
+    .bind(note_id)
+    .fetch_all(executor)
+    .await?)
 }
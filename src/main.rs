@@ -23,6 +23,16 @@ async fn main() -> std::io::Result<()> {
     // 初始化同步服务
     let sync_service = web::Data::new(sync::service::SyncService::new(db.clone()));
 
+    let sync_service_clone = sync_service.clone();
+    tokio::spawn(async move {
+        sync::service::purge_trash(sync_service_clone).await
+    });
+
+    let sync_service_clone = sync_service.clone();
+    tokio::spawn(async move {
+        sync::service::compact_tombstones(sync_service_clone).await
+    });
+
     // 初始化认证服务
     let auth_service = web::Data::new(auth::service::AuthService::new(db));
 
@@ -31,6 +41,11 @@ async fn main() -> std::io::Result<()> {
         auth::service::cleanup_expired_tokens(auth_service_clone).await
     });
 
+    let auth_service_clone = auth_service.clone();
+    tokio::spawn(async move {
+        auth::service::purge_deleted_accounts(auth_service_clone).await
+    });
+
     tracing::info!("Running api service");
     HttpServer::new(move || {
         App::new()
@@ -38,18 +53,46 @@ async fn main() -> std::io::Result<()> {
             .app_data(auth_service.clone())  
             .app_data(sync_service.clone())
             // 公开路由
+            .service(api::auth::jwks)
             .service(
                 web::scope("/api/auth")
                     .service(api::auth::register)
                     .service(api::auth::login)
+                    .service(api::auth::refresh)
+                    .service(api::auth::forgot_password)
+                    .service(api::auth::reset_password)
+                    .service(api::auth::verify_email)
+                    .service(api::auth::resend_verification_email)
+                    .service(api::auth::confirm_email_change)
+                    .service(api::auth::verify_mfa)
+                    // 需要登录的认证路由, 放在公开路由之后
+                    .service(
+                        web::scope("")
+                            .wrap(HttpAuthentication::bearer(middleware::auth::validator))
+                            .service(api::auth::change_password)
+                            .service(api::auth::request_email_change)
+                            .service(api::auth::enroll_totp)
+                            .service(api::auth::confirm_totp)
+                            .service(api::auth::disable_mfa)
+                            .service(api::auth::list_sessions)
+                            .service(api::auth::revoke_other_sessions)
+                            .service(api::auth::revoke_session)
+                    )
             )
             // 受保护路由
             .service(
                 web::scope("/api")
                     .wrap(HttpAuthentication::bearer(middleware::auth::validator))
                     .service(api::auth::get_me)
+                    .service(api::auth::update_me)
                     .service(api::auth::logout)
+                    .service(api::account::delete_account)
+                    .service(api::account::export_account)
                     .configure(api::sync::configure)
+                    .configure(api::tags::configure)
+                    .configure(api::notebooks::configure)
+                    .configure(api::shares::configure)
+                    .configure(api::trash::configure)
             )
     })
     .bind("0.0.0.0:8080")?
@@ -1,7 +1,7 @@
 use actix_web::{web, dev::ServiceRequest, Error, HttpMessage};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use crate::{auth::service::AuthService, log_error};
-use super::AuthToken;
+use super::{AuthSession, AuthToken};
 
 
 pub async fn validator(
@@ -13,12 +13,13 @@ pub async fn validator(
     let auth_service = req.app_data::<web::Data<AuthService>>()
         .expect("AuthService not found in app data");
     let token = credentials.token().trim_matches('"');
-    // validate_token 内部会检查黑名单
+    // validate_token 内部会检查会话是否已吊销
     match auth_service.validate_token(token).await {
         Ok(claims) => {
-            tracing::info!(user = %claims.sub, "Token validated successfully");
+            tracing::info!(user = %claims.sub, jti = %claims.jti, "Token validated successfully");
             req.extensions_mut().insert(claims.sub);
             req.extensions_mut().insert(AuthToken(token.to_string()));
+            req.extensions_mut().insert(AuthSession(claims.sid));
             Ok(req)
         }
         Err(e) => {
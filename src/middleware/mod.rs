@@ -8,4 +8,14 @@ impl AuthToken {
     pub fn token(&self) -> &str {
         &self.0
     }
-}
\ No newline at end of file
+}
+
+/// 当前请求所在的会话
+#[derive(Debug, Clone)]
+pub struct AuthSession(String);
+
+impl AuthSession {
+    pub fn id(&self) -> &str {
+        &self.0
+    }
+}
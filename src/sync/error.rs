@@ -4,6 +4,8 @@ use derive_more::Display;
 use sqlx::Error as SqlxError;
 use actix_web::{HttpResponse, ResponseError};
 
+use super::model::{Note, Notebook};
+
 #[derive(Debug, Display)]
 pub enum SyncError {
     #[display("Invalid credentials")]
@@ -21,14 +23,44 @@ pub enum SyncError {
     #[display("Database error: {}", _0)]
     DatabaseError(SqlxError),
 
-    // #[display("User not found")]
-    // UserNotFound,
+    #[display("User not found")]
+    UserNotFound,
 
     // #[display("User already exists")]
     // UserExists,
 
     #[display("Unauthorized")]
     Unauthorized,
+
+    #[display("Permission denied")]
+    Forbidden,
+
+    #[display("Note not found")]
+    NotFound,
+
+    #[display("Note revision conflict")]
+    Conflict(Box<Note>),
+
+    #[display("Invalid sync cursor")]
+    InvalidCursor,
+
+    #[display("Invalid request: {}", _0)]
+    InvalidRequest(String),
+
+    #[display("Tag not found")]
+    TagNotFound,
+
+    #[display("Revision not found")]
+    RevisionNotFound,
+
+    #[display("Notebook not found")]
+    NotebookNotFound,
+
+    #[display("Notebook revision conflict")]
+    NotebookConflict(Box<Notebook>),
+
+    #[display("Share not found")]
+    ShareNotFound,
 }
 
 impl ResponseError for SyncError {
@@ -39,9 +71,20 @@ impl ResponseError for SyncError {
             // SyncError::JwtValidationError(_) => HttpResponse::Unauthorized().json("Invalid token"),
             // SyncError::PasswordHashingError(_) => HttpResponse::InternalServerError().json("Password processing failed"),
             SyncError::DatabaseError(_) => HttpResponse::InternalServerError().json("Database operation failed"),
-            // SyncError::UserNotFound => HttpResponse::NotFound().json("User not found"),
+            SyncError::UserNotFound => HttpResponse::NotFound().json("User not found"),
             // SyncError::UserExists => HttpResponse::Conflict().json("User already exists"),
             SyncError::Unauthorized => HttpResponse::Unauthorized().finish(),
+            SyncError::Forbidden => HttpResponse::Forbidden().json("Permission denied"),
+            SyncError::NotFound => HttpResponse::NotFound().json("Note not found"),
+            // 返回服务端当前笔记, 由客户端合并
+            SyncError::Conflict(note) => HttpResponse::Conflict().json(note),
+            SyncError::InvalidCursor => HttpResponse::BadRequest().json("Invalid sync cursor"),
+            SyncError::InvalidRequest(message) => HttpResponse::BadRequest().json(message),
+            SyncError::TagNotFound => HttpResponse::NotFound().json("Tag not found"),
+            SyncError::RevisionNotFound => HttpResponse::NotFound().json("Revision not found"),
+            SyncError::NotebookNotFound => HttpResponse::NotFound().json("Notebook not found"),
+            SyncError::NotebookConflict(notebook) => HttpResponse::Conflict().json(notebook),
+            SyncError::ShareNotFound => HttpResponse::NotFound().json("Share not found"),
         }
     }
 }
@@ -1,20 +1,26 @@
 use std::collections::HashSet;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::{DateTime, Utc};
-use serde::{Serialize, Deserialize};
+use serde::{Deserializer, Serialize, Deserialize};
 use sqlx::FromRow;
 
+use super::error::SyncError;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Note {
     pub id: String,
     pub user_id: String,
+    pub notebook_id: Option<String>,
     pub title: String,
     pub content: String,
     pub tags: Vec<String>,
+    pub revision: i64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+/// notes 表的记录, 接口返回附带标签的 [`Note`]
+#[derive(Debug, FromRow)]
 pub struct NoteRow {
     pub id: String,
     pub user_id: String,
@@ -22,11 +28,32 @@ pub struct NoteRow {
     pub content: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub revision: i64,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub notebook_id: Option<String>,
+}
+
+impl NoteRow {
+    pub fn into_note(self, tags: Vec<String>) -> Note {
+        Note {
+            id: self.id,
+            user_id: self.user_id,
+            notebook_id: self.notebook_id,
+            title: self.title,
+            content: self.content,
+            tags,
+            revision: self.revision,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NoteCreate {
     pub title: String,
+    #[serde(default)]
+    pub notebook_id: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -35,6 +62,13 @@ pub struct NoteUpdate {
     pub title: Option<String>,
     pub content: Option<String>,
     pub tags: Option<HashSet<String>>,
+    /// 缺省时不移动, 为 null 时移出笔记本
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub notebook_id: Option<Option<String>>,
+    /// 客户端修改所基于的版本号, 与服务端不一致时返回冲突
+    ///
+    /// 省略时不做冲突检测, 直接覆盖服务端版本 (后写者胜), 只应在客户端明确要求强制覆盖时省略
+    pub base_revision: Option<i64>,
     pub updated_at: DateTime<Utc>
 }
 
@@ -43,19 +77,574 @@ pub struct NoteImport {
     pub title: String,
     pub content: String,
     pub tags: HashSet<String>,
+    #[serde(default)]
+    pub notebook_id: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>
 }
 
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteSort {
+    #[default]
+    UpdatedAt,
+    CreatedAt,
+    Title,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteListQuery {
+    #[serde(default)]
+    pub sort: NoteSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub tag: Option<String>,
+    pub notebook_id: Option<String>,
+    /// 按 updated_at 过滤的时间范围
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    /// 上一页返回的 next_cursor
+    pub cursor: Option<String>,
+}
+
+/// 列表分页游标, 记录排序方式和上一页最后一条的排序键和 id
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteListCursor {
+    pub sort: NoteSort,
+    pub order: SortOrder,
+    pub key: String,
+    pub id: String,
+}
+
+impl NoteListCursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("Failed to serialize cursor"))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, SyncError> {
+        let cursor: Self = URL_SAFE_NO_PAD.decode(cursor).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or(SyncError::InvalidCursor)?;
+
+        // 按时间排序的键必须是合法时间, 不交给数据库转换
+        if cursor.sort != NoteSort::Title && cursor.time_key().is_none() {
+            return Err(SyncError::InvalidCursor);
+        }
+        Ok(cursor)
+    }
+
+    /// 按时间排序时的排序键
+    pub fn time_key(&self) -> Option<DateTime<Utc>> {
+        match self.sort {
+            NoteSort::UpdatedAt | NoteSort::CreatedAt => DateTime::parse_from_rfc3339(&self.key).ok()
+                .map(|key| key.with_timezone(&Utc)),
+            NoteSort::Title => None,
+        }
+    }
+}
+
+#[derive(Debug, FromRow)]
+pub struct NoteSummaryRow {
+    pub id: String,
+    pub notebook_id: Option<String>,
+    pub title: String,
+    pub preview: String,
+    pub revision: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteSummary {
+    pub id: String,
+    pub notebook_id: Option<String>,
+    pub title: String,
+    pub tags: Vec<String>,
+    /// 正文开头的预览
+    pub preview: String,
+    pub revision: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteListResponse {
+    pub notes: Vec<NoteSummary>,
+    /// 为空表示没有下一页
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteSearchQuery {
+    pub q: String,
+    pub tag: Option<String>,
+    /// 按 updated_at 过滤的时间范围
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct NoteSearchRow {
+    pub id: String,
+    pub title: String,
+    pub snippet: String,
+    pub rank: f32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteSearchHit {
+    pub id: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    /// 匹配片段, 命中词以 <mark></mark> 包裹
+    pub snippet: String,
+    pub rank: f32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteSearchResponse {
+    pub hits: Vec<NoteSearchHit>,
+}
+
+/// 笔记的某个版本, archived_at 为空表示当前版本
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct NoteRevision {
+    pub note_id: String,
+    pub revision: i64,
+    pub title: String,
+    pub content: String,
+    pub tags: Vec<String>,
+    pub updated_at: DateTime<Utc>,
+    pub archived_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct NoteRevisionSummary {
+    pub revision: i64,
+    pub title: String,
+    pub updated_at: DateTime<Utc>,
+    pub archived_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteRevisionDiffQuery {
+    pub from: i64,
+    pub to: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteRevisionDiff {
+    pub from: i64,
+    pub to: i64,
+    /// unified diff 格式
+    pub title_diff: String,
+    pub content_diff: String,
+    pub tags_added: Vec<String>,
+    pub tags_removed: Vec<String>,
+}
+
+/// 移动笔记, notebook_id 为空时移出笔记本
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteMove {
+    pub notebook_id: Option<String>,
+    pub base_revision: Option<i64>,
+}
+
+/// 笔记本, 客户端根据 parent_id 还原层级
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Notebook {
+    pub id: String,
+    pub parent_id: Option<String>,
+    pub name: String,
+    pub revision: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotebookCreate {
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotebookUpdate {
+    pub name: String,
+    pub base_revision: Option<i64>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// 移动笔记本, parent_id 为空时移到顶层
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotebookMove {
+    pub parent_id: Option<String>,
+    pub base_revision: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotebookDeleteResponse {
+    pub deleted_notebooks: usize,
+    /// 笔记本中的笔记移入回收站
+    pub trashed_notes: usize,
+}
+
+/// 共享权限, write 包含 read
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum SharePermission {
+    Read,
+    Write,
+}
+
+/// 按邮箱共享笔记或笔记本, note_id 和 notebook_id 二选一
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShareCreate {
+    pub email: String,
+    pub note_id: Option<String>,
+    pub notebook_id: Option<String>,
+    pub permission: SharePermission,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Share {
+    pub id: String,
+    pub note_id: Option<String>,
+    pub notebook_id: Option<String>,
+    pub owner_id: String,
+    pub owner_email: String,
+    pub recipient_id: String,
+    pub recipient_email: String,
+    pub permission: SharePermission,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 回收站中的笔记
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct TrashedNote {
+    pub id: String,
+    pub title: String,
+    pub preview: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// 导出的回收站笔记, 包含完整内容
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedTrashNote {
+    #[serde(flatten)]
+    pub note: Note,
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// 用户的全部笔记数据, 用于导出
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotesExport {
+    pub notebooks: Vec<Notebook>,
+    pub notes: Vec<Note>,
+    pub trash: Vec<ExportedTrashNote>,
+    pub revisions: Vec<NoteRevision>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmptyTrashResponse {
+    pub purged_notes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct TagSummary {
+    pub tag: String,
+    pub note_count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagRename {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagMerge {
+    pub source: String,
+    pub target: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagChangeResponse {
+    pub affected_notes: usize,
+}
+
+/// 客户端待上传的本地变更
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SyncOperation {
+    Create {
+        note_id: String,
+        #[serde(flatten)]
+        note: NoteCreate,
+    },
+    Update {
+        note_id: String,
+        #[serde(flatten)]
+        update: NoteUpdate,
+    },
+    Delete {
+        note_id: String,
+    },
+    Tags {
+        note_id: String,
+        tags: HashSet<String>,
+        base_revision: Option<i64>,
+        updated_at: DateTime<Utc>,
+    },
+    Move {
+        note_id: String,
+        notebook_id: Option<String>,
+        base_revision: Option<i64>,
+        updated_at: DateTime<Utc>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncOperationStatus {
+    Accepted,
+    Conflict,
+    Rejected,
+}
+
+/// 单个上传操作的处理结果, 与请求中的操作一一对应
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncOperationResult {
+    pub note_id: String,
+    pub status: SyncOperationStatus,
+    /// 接受时为更新后的笔记, 冲突时为服务端当前笔记
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<Note>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl SyncOperationResult {
+    pub fn accepted(note_id: String, note: Option<Note>) -> Self {
+        Self { note_id, status: SyncOperationStatus::Accepted, note, reason: None }
+    }
+
+    pub fn conflict(note_id: String, note: Note) -> Self {
+        Self { note_id, status: SyncOperationStatus::Conflict, note: Some(note), reason: None }
+    }
+
+    pub fn rejected(note_id: String, reason: &str) -> Self {
+        Self { note_id, status: SyncOperationStatus::Rejected, note: None, reason: Some(reason.to_string()) }
+    }
+}
+
+/// 变更日志中的变更类型
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// 变更日志记录的对象类型
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum ChangeEntity {
+    Note,
+    Notebook,
+}
+
+/// 已提交的笔记变更, 同时作为实时通知事件下发
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct NoteChange {
+    #[serde(skip)]
+    pub user_id: String,
+    pub seq: i64,
+    pub entity: ChangeEntity,
+    /// entity 为 notebook 时是笔记本ID
+    pub note_id: String,
+    pub kind: ChangeKind,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct NoteChangeRow {
+    pub entity: ChangeEntity,
+    pub note_id: String,
+    pub kind: ChangeKind,
+    pub seq: i64,
+}
+
+/// 同步游标, 对客户端不透明
+///
+/// 内部为用户变更序号和签发时的压缩序号
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncCursor {
+    pub seq: i64,
+    pub compacted_seq: i64,
+}
+
+impl SyncCursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("seq:{}:{}", self.seq, self.compacted_seq))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, SyncError> {
+        let decoded = URL_SAFE_NO_PAD.decode(cursor).ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or(SyncError::InvalidCursor)?;
+        let mut parts = decoded.strip_prefix("seq:").ok_or(SyncError::InvalidCursor)?.split(':');
+
+        let seq = parts.next().and_then(|seq| seq.parse().ok());
+        // 旧版游标不含压缩序号
+        let compacted_seq = parts.next().map_or(Some(0), |seq| seq.parse().ok());
+        match (seq, compacted_seq, parts.next()) {
+            (Some(seq), Some(compacted_seq), None) if seq >= 0 && compacted_seq >= 0 => Ok(Self { seq, compacted_seq }),
+            _ => Err(SyncError::InvalidCursor),
+        }
+    }
+
+    /// 游标之后的删除记录已被压缩, 增量同步无法保证删除完整下发
+    pub fn is_stale(&self, compacted_seq: i64) -> bool {
+        self.seq < compacted_seq && self.compacted_seq < compacted_seq
+    }
+}
+
+/// 一页变更
+#[derive(Debug)]
+pub struct SyncPage {
+    pub notebooks: Vec<Notebook>,
+    pub deleted_notebook_ids: Vec<String>,
+    pub notes: Vec<Note>,
+    pub deleted_note_ids: Vec<String>,
+    /// 本页最后一条变更的序号, 没有变更时为请求的序号
+    pub last_seq: i64,
+    pub has_more: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SyncRequest {
-    pub last_sync_time: Option<DateTime<Utc>>,
+    /// 上次同步返回的游标, 为空时全量同步
+    pub cursor: Option<String>,
     pub device_id: String,
+    #[serde(default)]
+    pub changes: Vec<SyncOperation>,
+    /// 单页最多返回的变更数
+    pub limit: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SyncResponse {
+    pub results: Vec<SyncOperationResult>,
+    /// 父笔记本可能在后续页下发, 客户端应在整页应用后再还原层级
+    pub notebooks: Vec<Notebook>,
+    pub deleted_notebook_ids: Vec<String>,
     pub notes: Vec<Note>,
     pub deleted_note_ids: Vec<String>,
-    pub current_time: DateTime<Utc>,
+    /// 下一页 (或下次同步) 的游标
+    pub cursor: String,
+    /// 为 true 时客户端应立即用返回的游标继续拉取
+    pub has_more: bool,
+    /// 客户端游标早于最早保留的删除记录, 需要用返回的游标全量拉取并与本地数据对账
+    pub full_resync_required: bool,
+}
+
+// 区分字段缺失 (None) 和显式的 null (Some(None))
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_cursor_round_trip() {
+        let cursor = SyncCursor { seq: 42, compacted_seq: 7 };
+        assert_eq!(SyncCursor::decode(&cursor.encode()).unwrap(), cursor);
+    }
+
+    #[test]
+    fn sync_cursor_decodes_legacy_format() {
+        let cursor = SyncCursor::decode(&URL_SAFE_NO_PAD.encode("seq:42")).unwrap();
+        assert_eq!(cursor, SyncCursor { seq: 42, compacted_seq: 0 });
+    }
+
+    #[test]
+    fn sync_cursor_rejects_invalid_input() {
+        for raw in ["", "seq:", "seq:abc", "seq:-1", "seq:1:-1", "seq:1:2:3", "42", "seq:1:x"] {
+            assert!(
+                matches!(SyncCursor::decode(&URL_SAFE_NO_PAD.encode(raw)), Err(SyncError::InvalidCursor)),
+                "{raw:?} should be rejected",
+            );
+        }
+        assert!(matches!(SyncCursor::decode("not base64!"), Err(SyncError::InvalidCursor)));
+    }
+
+    #[test]
+    fn sync_cursor_is_stale_only_when_deletions_were_compacted_after_it() {
+        let cursor = SyncCursor { seq: 10, compacted_seq: 5 };
+        assert!(!cursor.is_stale(5));
+        assert!(!cursor.is_stale(10));
+        assert!(cursor.is_stale(11));
+        // 签发后没有新的压缩, 即使游标落后于压缩序号也不需要全量同步
+        assert!(!SyncCursor { seq: 3, compacted_seq: 8 }.is_stale(8));
+    }
+
+    fn note_list_cursor(sort: NoteSort, key: &str) -> NoteListCursor {
+        NoteListCursor { sort, order: SortOrder::Desc, key: key.to_string(), id: "note-1".to_string() }
+    }
+
+    #[test]
+    fn note_list_cursor_round_trip() {
+        let encoded = note_list_cursor(NoteSort::CreatedAt, "2024-05-01T12:00:00Z").encode();
+        let cursor = NoteListCursor::decode(&encoded).unwrap();
+        assert_eq!(cursor.sort, NoteSort::CreatedAt);
+        assert_eq!(cursor.order, SortOrder::Desc);
+        assert_eq!(cursor.id, "note-1");
+        assert_eq!(cursor.time_key(), Some("2024-05-01T12:00:00Z".parse().unwrap()));
+    }
+
+    #[test]
+    fn note_list_cursor_requires_time_key_for_time_sorts() {
+        for sort in [NoteSort::UpdatedAt, NoteSort::CreatedAt] {
+            let encoded = note_list_cursor(sort, "yesterday").encode();
+            assert!(matches!(NoteListCursor::decode(&encoded), Err(SyncError::InvalidCursor)));
+        }
+        // 按标题排序时键是任意字符串
+        let cursor = NoteListCursor::decode(&note_list_cursor(NoteSort::Title, "yesterday").encode()).unwrap();
+        assert_eq!(cursor.key, "yesterday");
+        assert_eq!(cursor.time_key(), None);
+    }
+
+    #[test]
+    fn note_list_cursor_rejects_malformed_input() {
+        assert!(matches!(NoteListCursor::decode("not base64!"), Err(SyncError::InvalidCursor)));
+        let encoded = URL_SAFE_NO_PAD.encode(r#"{"sort":"updated_at","key":"2024-05-01T12:00:00Z"}"#);
+        assert!(matches!(NoteListCursor::decode(&encoded), Err(SyncError::InvalidCursor)));
+    }
 }
@@ -0,0 +1,37 @@
+use tokio::sync::broadcast;
+
+use super::model::NoteChange;
+
+// 广播缓冲区大小, 订阅者落后超过该数量时会收到 Lagged 错误
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// 笔记变更通知
+///
+/// 所有用户共用一个广播通道, 订阅者按 user_id 过滤自己的事件
+pub struct ChangeNotifier {
+    sender: broadcast::Sender<NoteChange>,
+}
+
+impl ChangeNotifier {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NoteChange> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish(&self, changes: Vec<NoteChange>) {
+        for change in changes {
+            // 没有订阅者时发送失败, 直接忽略
+            let _ = self.sender.send(change);
+        }
+    }
+}
+
+impl Default for ChangeNotifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
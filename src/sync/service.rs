@@ -1,22 +1,60 @@
-use crate::{database::{Database, SyncDatabase}, sync::{error::SyncError, model::{Note, NoteCreate, NoteImport, NoteUpdate, SyncRequest, SyncResponse}}};
+use std::collections::HashSet;
+use chrono::SecondsFormat;
+use similar::TextDiff;
+use tokio::sync::broadcast;
 
+use crate::{database::{Database, SyncDatabase}, sync::{error::SyncError, model::{ChangeEntity, EmptyTrashResponse, Note, NoteMove, Notebook, NotebookCreate, NotebookDeleteResponse, NotebookMove, NotebookUpdate, NotesExport, NoteChange, Share, ShareCreate, NoteCreate, NoteImport, NoteListCursor, NoteListQuery, NoteListResponse, NoteRevision, NoteRevisionDiff, NoteRevisionDiffQuery, NoteRevisionSummary, NoteSearchQuery, NoteSearchResponse, NoteSort, NoteUpdate, SyncCursor, SyncRequest, SyncResponse, TagChangeResponse, TagMerge, TagRename, TagSummary, TrashedNote}, notifier::ChangeNotifier}};
+use crate::{log_error, utils::config::env_or};
 
+// 同步分页大小
+const DEFAULT_SYNC_LIMIT: i64 = 500;
+const MAX_SYNC_LIMIT: i64 = 1000;
+// 列表分页大小
+const DEFAULT_LIST_LIMIT: i64 = 50;
+const MAX_LIST_LIMIT: i64 = 200;
+// 搜索分页大小
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
 
 pub struct SyncService {
     db: Database,
+    notifier: ChangeNotifier,
+    trash_retention: i64,   // 天
+    tombstone_retention: i64,   // 天
 }
 
 impl SyncService {
     pub fn new(db: Database) -> Self {
-        Self { db }
+        let trash_retention = env_or("TRASH_RETENTION_DAYS", 30);
+        let tombstone_retention = env_or("TOMBSTONE_RETENTION_DAYS", 90);
+
+        Self { db, notifier: ChangeNotifier::new(), trash_retention, tombstone_retention }
+    }
+
+    // 订阅所有用户的笔记变更, 由调用方按 user_id 过滤
+    pub fn subscribe(&self) -> broadcast::Receiver<NoteChange> {
+        self.notifier.subscribe()
+    }
+
+    // 按序号顺序获取某个序号之后的变更, 用于事件流断线重连
+    // 该序号之后的删除记录已被压缩时返回 None
+    pub async fn changes_since(&self, user_id: &str, seq: i64, limit: i64) -> Result<Option<Vec<NoteChange>>, SyncError> {
+        if seq < self.db.get_compacted_seq(user_id).await? {
+            return Ok(None);
+        }
+        self.db.get_changes_since(user_id, seq, limit).await.map(Some)
     }
 
     pub async fn create_note(&self, user_id: &str, note_id: &str, note: NoteCreate) -> Result<(), SyncError> {
-        self.db.create_note(user_id, note_id, &note).await
+        let changes = self.db.create_note(user_id, note_id, &note).await?;
+        self.notifier.publish(changes);
+        Ok(())
     }
 
     pub async fn import_note(&self, user_id: &str, note_id: &str, note: NoteImport) -> Result<(), SyncError> {
-        self.db.import_note(user_id, note_id, &note).await
+        let changes = self.db.import_note(user_id, note_id, &note).await?;
+        self.notifier.publish(changes);
+        Ok(())
     }
 
     pub async fn get_note(&self, user_id: &str, note_id: &str) -> Result<Note, SyncError> {
@@ -24,21 +62,326 @@ impl SyncService {
     }
 
     pub async fn update_note(&self, user_id: &str, note_id: &str, update: NoteUpdate) -> Result<Note, SyncError> {
-        self.db.update_note(user_id, note_id, update).await
+        let (note, changes) = self.db.update_note(user_id, note_id, update).await?;
+        self.notifier.publish(changes);
+        Ok(note)
     }
 
     pub async fn delete_note(&self, user_id: &str, note_id: &str) -> Result<(), SyncError> {
-        self.db.delete_note(user_id, note_id).await
+        let changes = self.db.delete_note(user_id, note_id).await?;
+        self.notifier.publish(changes);
+        Ok(())
+    }
+
+    pub async fn list_notes(&self, user_id: &str, query: NoteListQuery) -> Result<NoteListResponse, SyncError> {
+        let cursor = query.cursor.as_deref().map(NoteListCursor::decode).transpose()?;
+        if let Some(cursor) = &cursor
+            && (cursor.sort != query.sort || cursor.order != query.order)
+        {
+            return Err(SyncError::InvalidCursor);
+        }
+
+        // 多取一条判断是否还有下一页
+        let limit = query.limit.unwrap_or(DEFAULT_LIST_LIMIT).clamp(1, MAX_LIST_LIMIT);
+        let mut notes = self.db.list_notes(user_id, &query, cursor.as_ref(), limit + 1).await?;
+
+        let next_cursor = if notes.len() as i64 > limit {
+            notes.truncate(limit as usize);
+            notes.last().map(|last| {
+                let key = match query.sort {
+                    NoteSort::UpdatedAt => last.updated_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+                    NoteSort::CreatedAt => last.created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+                    NoteSort::Title => last.title.clone(),
+                };
+                NoteListCursor { sort: query.sort, order: query.order, key, id: last.id.clone() }.encode()
+            })
+        } else {
+            None
+        };
+
+        Ok(NoteListResponse { notes, next_cursor })
+    }
+
+    pub async fn search_notes(&self, user_id: &str, query: NoteSearchQuery) -> Result<NoteSearchResponse, SyncError> {
+        if query.q.trim().is_empty() {
+            return Err(SyncError::InvalidRequest("Search query must not be empty".to_string()));
+        }
+
+        let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT);
+        let offset = query.offset.unwrap_or(0).max(0);
+        let hits = self.db.search_notes(user_id, &query, limit, offset).await?;
+
+        Ok(NoteSearchResponse { hits })
+    }
+
+    pub async fn list_note_revisions(&self, user_id: &str, note_id: &str) -> Result<Vec<NoteRevisionSummary>, SyncError> {
+        self.db.list_note_revisions(user_id, note_id).await
+    }
+
+    pub async fn get_note_revision(&self, user_id: &str, note_id: &str, revision: i64) -> Result<NoteRevision, SyncError> {
+        self.db.get_note_revision(user_id, note_id, revision).await
+    }
+
+    pub async fn diff_note_revisions(&self, user_id: &str, note_id: &str, query: NoteRevisionDiffQuery) -> Result<NoteRevisionDiff, SyncError> {
+        let from = self.db.get_note_revision(user_id, note_id, query.from).await?;
+        let to = self.db.get_note_revision(user_id, note_id, query.to).await?;
+
+        let from_label = format!("revision {}", from.revision);
+        let to_label = format!("revision {}", to.revision);
+        let unified_diff = |old: &str, new: &str| {
+            TextDiff::from_lines(old, new)
+                .unified_diff()
+                .context_radius(3)
+                .header(&from_label, &to_label)
+                .to_string()
+        };
+
+        let from_tags: HashSet<&String> = from.tags.iter().collect();
+        let to_tags: HashSet<&String> = to.tags.iter().collect();
+        let mut tags_added: Vec<String> = to_tags.difference(&from_tags).map(|t| t.to_string()).collect();
+        let mut tags_removed: Vec<String> = from_tags.difference(&to_tags).map(|t| t.to_string()).collect();
+        tags_added.sort();
+        tags_removed.sort();
+
+        Ok(NoteRevisionDiff {
+            from: from.revision,
+            to: to.revision,
+            title_diff: unified_diff(&from.title, &to.title),
+            content_diff: unified_diff(&from.content, &to.content),
+            tags_added,
+            tags_removed,
+        })
+    }
+
+    pub async fn restore_note_revision(&self, user_id: &str, note_id: &str, revision: i64) -> Result<Note, SyncError> {
+        let (note, changes) = self.db.restore_note_revision(user_id, note_id, revision).await?;
+        self.notifier.publish(changes);
+        Ok(note)
+    }
+
+    pub async fn list_trash(&self, user_id: &str) -> Result<Vec<TrashedNote>, SyncError> {
+        self.db.list_trash(user_id).await
+    }
+
+    pub async fn export_notes(&self, user_id: &str) -> Result<NotesExport, SyncError> {
+        self.db.export_notes(user_id).await
+    }
+
+    pub async fn restore_note(&self, user_id: &str, note_id: &str) -> Result<Note, SyncError> {
+        let (note, changes) = self.db.restore_note(user_id, note_id).await?;
+        self.notifier.publish(changes);
+        Ok(note)
+    }
+
+    pub async fn empty_trash(&self, user_id: &str) -> Result<EmptyTrashResponse, SyncError> {
+        let purged_notes = self.db.empty_trash(user_id, None).await?;
+        Ok(EmptyTrashResponse { purged_notes })
+    }
+
+    pub async fn purge_trashed_note(&self, user_id: &str, note_id: &str) -> Result<(), SyncError> {
+        match self.db.empty_trash(user_id, Some(note_id)).await? {
+            0 => Err(SyncError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn list_tags(&self, user_id: &str) -> Result<Vec<TagSummary>, SyncError> {
+        self.db.list_tags(user_id).await
+    }
+
+    pub async fn rename_tag(&self, user_id: &str, tag: &str, rename: TagRename) -> Result<TagChangeResponse, SyncError> {
+        validate_tag(&rename.name)?;
+        self.replace_tag(user_id, tag, Some(&rename.name)).await
+    }
+
+    pub async fn merge_tags(&self, user_id: &str, merge: TagMerge) -> Result<TagChangeResponse, SyncError> {
+        validate_tag(&merge.target)?;
+        self.replace_tag(user_id, &merge.source, Some(&merge.target)).await
+    }
+
+    pub async fn delete_tag(&self, user_id: &str, tag: &str) -> Result<TagChangeResponse, SyncError> {
+        self.replace_tag(user_id, tag, None).await
+    }
+
+    async fn replace_tag(&self, user_id: &str, tag: &str, new_tag: Option<&str>) -> Result<TagChangeResponse, SyncError> {
+        if new_tag == Some(tag) {
+            return Err(SyncError::InvalidRequest("New tag must differ from the old one".to_string()));
+        }
+
+        let changes = self.db.replace_tag(user_id, tag, new_tag).await?;
+        // 变更也会写入共享用户的日志, 只统计自己的
+        let affected_notes = changes.iter().filter(|c| c.user_id == user_id).count();
+        self.notifier.publish(changes);
+
+        Ok(TagChangeResponse { affected_notes })
+    }
+
+    pub async fn move_note(&self, user_id: &str, note_id: &str, note_move: NoteMove) -> Result<Note, SyncError> {
+        let update = NoteUpdate {
+            title: None,
+            content: None,
+            tags: None,
+            notebook_id: Some(note_move.notebook_id),
+            base_revision: note_move.base_revision,
+            updated_at: chrono::Utc::now(),
+        };
+        self.update_note(user_id, note_id, update).await
+    }
+
+    pub async fn list_notebooks(&self, user_id: &str) -> Result<Vec<Notebook>, SyncError> {
+        self.db.list_notebooks(user_id).await
+    }
+
+    pub async fn get_notebook(&self, user_id: &str, notebook_id: &str) -> Result<Notebook, SyncError> {
+        self.db.get_notebook(user_id, notebook_id).await
+    }
+
+    pub async fn create_notebook(&self, user_id: &str, notebook_id: &str, notebook: NotebookCreate) -> Result<Notebook, SyncError> {
+        validate_notebook_name(&notebook.name)?;
+        let (notebook, changes) = self.db.create_notebook(user_id, notebook_id, &notebook).await?;
+        self.notifier.publish(changes);
+        Ok(notebook)
+    }
+
+    pub async fn update_notebook(&self, user_id: &str, notebook_id: &str, update: NotebookUpdate) -> Result<Notebook, SyncError> {
+        validate_notebook_name(&update.name)?;
+        let (notebook, changes) = self.db.update_notebook(user_id, notebook_id, Some(&update.name), None, update.base_revision, update.updated_at).await?;
+        self.notifier.publish(changes);
+        Ok(notebook)
+    }
+
+    pub async fn move_notebook(&self, user_id: &str, notebook_id: &str, notebook_move: NotebookMove) -> Result<Notebook, SyncError> {
+        let parent_id = Some(notebook_move.parent_id.as_deref());
+        let (notebook, changes) = self.db.update_notebook(user_id, notebook_id, None, parent_id, notebook_move.base_revision, chrono::Utc::now()).await?;
+        self.notifier.publish(changes);
+        Ok(notebook)
+    }
+
+    pub async fn delete_notebook(&self, user_id: &str, notebook_id: &str) -> Result<NotebookDeleteResponse, SyncError> {
+        let changes = self.db.delete_notebook(user_id, notebook_id).await?;
+        let own_changes = changes.iter().filter(|c| c.user_id == user_id);
+        let deleted_notebooks = own_changes.clone().filter(|c| c.entity == ChangeEntity::Notebook).count();
+        let trashed_notes = own_changes.count() - deleted_notebooks;
+        self.notifier.publish(changes);
+
+        Ok(NotebookDeleteResponse { deleted_notebooks, trashed_notes })
+    }
+
+    pub async fn create_share(&self, owner_id: &str, share: ShareCreate) -> Result<Share, SyncError> {
+        let email = share.email.trim();
+        if email.is_empty() {
+            return Err(SyncError::InvalidRequest("Email is required".to_string()));
+        }
+        if share.note_id.is_some() == share.notebook_id.is_some() {
+            return Err(SyncError::InvalidRequest("Exactly one of note_id and notebook_id is required".to_string()));
+        }
+
+        let share_id = uuid::Uuid::new_v4().to_string();
+        let (share, changes) = self.db.create_share(owner_id, &share_id, email, share.note_id.as_deref(), share.notebook_id.as_deref(), share.permission).await?;
+        self.notifier.publish(changes);
+        Ok(share)
+    }
+
+    pub async fn list_shares(&self, owner_id: &str) -> Result<Vec<Share>, SyncError> {
+        self.db.list_shares(owner_id).await
+    }
+
+    pub async fn list_received_shares(&self, recipient_id: &str) -> Result<Vec<Share>, SyncError> {
+        self.db.list_received_shares(recipient_id).await
+    }
+
+    pub async fn delete_share(&self, user_id: &str, share_id: &str) -> Result<(), SyncError> {
+        let changes = self.db.delete_share(user_id, share_id).await?;
+        self.notifier.publish(changes);
+        Ok(())
     }
 
     pub async fn sync_notes(&self, user_id: &str, sync_request: SyncRequest) -> Result<SyncResponse, SyncError> {
-        let last_sync_time = sync_request.last_sync_time.unwrap_or(chrono::Utc::now() - chrono::Duration::days(365));
-        let now = chrono::Utc::now();
+        let cursor = sync_request.cursor.as_deref().map(SyncCursor::decode).transpose()?;
 
-        let (notes, deleted_note_ids) = self.db.get_sync_notes(user_id, last_sync_time).await?;
+        // 先应用客户端上传的变更, 再计算需要下发的变更
+        let results = if sync_request.changes.is_empty() {
+            Vec::new()
+        } else {
+            let (results, changes) = self.db.apply_sync_operations(user_id, sync_request.changes).await?;
+            self.notifier.publish(changes);
+            results
+        };
+
+        // 游标之后的删除记录已被压缩, 要求客户端从头全量同步
+        let compacted_seq = self.db.get_compacted_seq(user_id).await?;
+        // 没有游标即为全量同步, 不受压缩影响
+        let cursor = cursor.unwrap_or(SyncCursor { seq: 0, compacted_seq });
+        if cursor.is_stale(compacted_seq) {
+            tracing::warn!(user_id = %user_id, cursor_seq = cursor.seq, compacted_seq, "Sync cursor is older than retained tombstones");
+            return Ok(SyncResponse {
+                results,
+                notebooks: Vec::new(),
+                deleted_notebook_ids: Vec::new(),
+                notes: Vec::new(),
+                deleted_note_ids: Vec::new(),
+                cursor: SyncCursor { seq: 0, compacted_seq }.encode(),
+                has_more: true,
+                full_resync_required: true,
+            });
+        }
+
+        let limit = sync_request.limit.unwrap_or(DEFAULT_SYNC_LIMIT).clamp(1, MAX_SYNC_LIMIT);
+        let page = self.db.get_sync_notes(user_id, cursor.seq, limit).await?;
 
         Ok(SyncResponse {
-            notes, deleted_note_ids, current_time: now
+            results,
+            notebooks: page.notebooks,
+            deleted_notebook_ids: page.deleted_notebook_ids,
+            notes: page.notes,
+            deleted_note_ids: page.deleted_note_ids,
+            cursor: SyncCursor { seq: page.last_seq, compacted_seq }.encode(),
+            has_more: page.has_more,
+            full_resync_required: false,
         })
     }
-}
\ No newline at end of file
+}
+
+pub async fn purge_trash(sync_service: actix_web::web::Data<SyncService>) {
+    // 每小时清理一次超过保留期的回收站笔记
+    loop {
+        let before = chrono::Utc::now() - chrono::Duration::days(sync_service.trash_retention);
+        match sync_service.db.purge_trash(before).await {
+            Ok(purged) => tracing::info!(purged, "Purged expired trash"),
+            Err(e) => {
+                log_error!(e, "Failed to purge trash");
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(3600)).await;
+    }
+}
+
+pub async fn compact_tombstones(sync_service: actix_web::web::Data<SyncService>) {
+    // 每天压缩一次超过保留期的删除记录
+    loop {
+        let before = chrono::Utc::now() - chrono::Duration::days(sync_service.tombstone_retention);
+        match sync_service.db.compact_tombstones(before).await {
+            Ok(compacted) => tracing::info!(compacted, "Compacted note change log"),
+            Err(e) => {
+                log_error!(e, "Failed to compact tombstones");
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(86400)).await;
+    }
+}
+
+// 笔记本名称长度受 notebooks.name 列限制
+fn validate_notebook_name(name: &str) -> Result<(), SyncError> {
+    if name.trim().is_empty() || name.chars().count() > 100 {
+        return Err(SyncError::InvalidRequest("Notebook name must be 1 to 100 characters".to_string()));
+    }
+    Ok(())
+}
+
+// 标签长度受 note_tags.tag 列限制
+fn validate_tag(tag: &str) -> Result<(), SyncError> {
+    if tag.trim().is_empty() || tag.chars().count() > 36 {
+        return Err(SyncError::InvalidRequest("Tag must be 1 to 36 characters".to_string()));
+    }
+    Ok(())
+}
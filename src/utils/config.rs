@@ -0,0 +1,6 @@
+use std::{env, str::FromStr};
+
+/// 读取环境变量配置, 未设置或无法解析时使用默认值
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
@@ -0,0 +1,58 @@
+use std::{env, fs, io, path::PathBuf};
+
+use super::config::env_or;
+
+/// 待发送的邮件
+#[derive(Debug)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// 邮件发送接口, 部署时可替换为 SMTP 等实现
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> io::Result<()>;
+}
+
+/// 根据 `MAILER` 环境变量选择实现, 默认只写日志
+pub fn mailer_from_env() -> Box<dyn Mailer> {
+    match env::var("MAILER").as_deref() {
+        Ok("file") => {
+            let dir = env_or("MAIL_DIR", "mail".to_string());
+            Box::new(FileMailer::new(dir))
+        }
+        _ => Box::new(LogMailer),
+    }
+}
+
+/// 把邮件内容写入日志, 用于本地开发
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, email: &Email) -> io::Result<()> {
+        tracing::info!(to = %email.to, subject = %email.subject, body = %email.body, "Sending email");
+        Ok(())
+    }
+}
+
+/// 每封邮件写成目录下的一个文件, 用于本地测试
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("{}-{}.eml", chrono::Utc::now().format("%Y%m%d%H%M%S"), uuid::Uuid::new_v4()));
+        fs::write(&path, format!("To: {}\nSubject: {}\n\n{}\n", email.to, email.subject, email.body))?;
+        tracing::info!(to = %email.to, path = %path.display(), "Email written to file");
+        Ok(())
+    }
+}
@@ -1 +1,3 @@
-pub mod logging;
\ No newline at end of file
+pub mod config;
+pub mod logging;
+pub mod mailer;
\ No newline at end of file